@@ -0,0 +1,210 @@
+use crate::luma::LumaImage;
+use crate::position_markers::is_white;
+
+const VARIANCE_THRESHOLD: f64 = 0.5;
+const SEARCH_RADII_IN_MODULES: [f64; 3] = [4., 8., 16.];
+/// Found alignment patterns further away from their predicted center are assumed to be
+/// misdetections
+pub(crate) const MAX_ALIGNMENT_PATTERN_OFFSET_IN_MODULES: f64 = 4.;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignmentPattern {
+  /// Center of the pattern, with pixel centers at integer coordinates like in every other stage
+  pub center: (f64, f64),
+  pub size: f64,
+}
+
+#[derive(Debug)]
+struct Run {
+  white: bool,
+  start: u32,
+  length: u32,
+}
+
+fn runs_in_row<I: LumaImage>(image: &I, y: u32, x_start: u32, x_end: u32) -> Vec<Run> {
+  let mut runs: Vec<Run> = vec![];
+
+  for x in x_start..x_end {
+    let white = is_white(image.luma(x, y));
+    match runs.last_mut() {
+      Some(run) if run.white == white => run.length += 1,
+      _ => runs.push(Run {
+        white,
+        start: x,
+        length: 1,
+      }),
+    }
+  }
+  runs
+}
+
+fn matches_module_size(length: u32, module_size: f64) -> bool {
+  f64::abs(f64::from(length) - module_size) < VARIANCE_THRESHOLD * module_size
+}
+
+/// Length of the run of pixels with the given color starting at `(x, y)` and moving by `step`
+/// rows, not including the starting pixel
+fn vertical_run<I: LumaImage>(image: &I, x: u32, y: u32, step: i64, white: bool) -> u32 {
+  let mut length = 0;
+  let mut current = i64::from(y) + step;
+
+  while current >= 0
+    && current < i64::from(image.height())
+    && is_white(image.luma(x, current as u32)) == white
+  {
+    length += 1;
+    current += step;
+  }
+  length
+}
+
+/// Checks the column through a candidate center for the white ring, black center, white ring
+/// sequence and returns the vertical center of the black center module
+fn cross_check_vertical<I: LumaImage>(image: &I, x: u32, y: u32, module_size: f64) -> Option<f64> {
+  if is_white(image.luma(x, y)) {
+    return None;
+  }
+  let black_above = vertical_run(image, x, y, -1, false);
+  let black_below = vertical_run(image, x, y, 1, false);
+  let black_length = black_above + black_below + 1;
+  let top = y - black_above;
+  let bottom = y + black_below;
+  let white_above = vertical_run(image, x, top, -1, true);
+  let white_below = vertical_run(image, x, bottom, 1, true);
+
+  let bounded_above = top > white_above;
+  let bounded_below = bottom + white_below + 1 < image.height();
+  if bounded_above
+    && bounded_below
+    && matches_module_size(black_length, module_size)
+    && matches_module_size(white_above, module_size)
+    && matches_module_size(white_below, module_size)
+  {
+    Some(f64::from(top + bottom) / 2.)
+  } else {
+    None
+  }
+}
+
+fn find_candidates<I: LumaImage>(
+  image: &I,
+  predicted_center: (f64, f64),
+  module_size: f64,
+  radius: f64,
+) -> Vec<(f64, f64)> {
+  let clamp = |value: f64, max: u32| (value.max(0.) as u32).min(max);
+  let x_start = clamp(predicted_center.0 - radius, image.width());
+  let x_end = clamp(predicted_center.0 + radius, image.width());
+  let y_start = clamp(predicted_center.1 - radius, image.height());
+  let y_end = clamp(predicted_center.1 + radius, image.height());
+  let mut candidates = vec![];
+
+  for y in y_start..y_end {
+    let runs = runs_in_row(image, y, x_start, x_end);
+    // A row through the center reads black border, white ring, black center, white ring and
+    // black border again. The borders are only used to make sure the rings are complete.
+    for window in runs.windows(5) {
+      let (white1, center, white2) = (&window[1], &window[2], &window[3]);
+      if !white1.white
+        || center.white
+        || !matches_module_size(white1.length, module_size)
+        || !matches_module_size(center.length, module_size)
+        || !matches_module_size(white2.length, module_size)
+      {
+        continue;
+      }
+      let center_x = f64::from(center.start) + f64::from(center.length - 1) / 2.;
+      if let Some(center_y) = cross_check_vertical(image, center_x.round() as u32, y, module_size) {
+        candidates.push((center_x, center_y));
+      }
+    }
+  }
+  candidates
+}
+
+/// Searches the thresholded image for an alignment pattern around its predicted center,
+/// widening the search area until one is found. Of all candidates the one closest to the
+/// prediction is used, averaged with the candidates that belong to the same pattern.
+pub fn find_alignment_pattern<I: LumaImage>(
+  image: &I,
+  predicted_center: (f64, f64),
+  module_size: f64,
+) -> Option<AlignmentPattern> {
+  let distance_to_prediction = |point: &(f64, f64)| {
+    f64::powi(point.0 - predicted_center.0, 2) + f64::powi(point.1 - predicted_center.1, 2)
+  };
+
+  SEARCH_RADII_IN_MODULES
+    .iter()
+    .find_map(|radius_in_modules| {
+      let candidates = find_candidates(
+        image,
+        predicted_center,
+        module_size,
+        radius_in_modules * module_size,
+      );
+      let closest = candidates.iter().min_by(|a, b| {
+        distance_to_prediction(a)
+          .partial_cmp(&distance_to_prediction(b))
+          .unwrap()
+      })?;
+      let same_pattern: Vec<_> = candidates
+        .iter()
+        .filter(|candidate| {
+          f64::abs(candidate.0 - closest.0) < module_size
+            && f64::abs(candidate.1 - closest.1) < module_size
+        })
+        .collect();
+      let number_of_candidates = same_pattern.len() as f64;
+
+      Some(AlignmentPattern {
+        center: (
+          same_pattern.iter().map(|c| c.0).sum::<f64>() / number_of_candidates,
+          same_pattern.iter().map(|c| c.1).sum::<f64>() / number_of_candidates,
+        ),
+        size: 5. * module_size,
+      })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::luma::LumaBuffer;
+
+  fn draw_alignment_pattern(image: &mut LumaBuffer, left: u32, top: u32, module_size: u32) {
+    for module_x in 0..5 {
+      for module_y in 0..5 {
+        let ring = i32::max((module_x as i32 - 2).abs(), (module_y as i32 - 2).abs());
+        let value = if ring == 1 { 255 } else { 0 };
+        for x in 0..module_size {
+          for y in 0..module_size {
+            image.put_luma(
+              left + module_x * module_size + x,
+              top + module_y * module_size + y,
+              value,
+            );
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn it_should_find_an_alignment_pattern_near_its_prediction() {
+    let mut image = LumaBuffer::from_fn(100, 100, |_, _| 255);
+    draw_alignment_pattern(&mut image, 40, 50, 4);
+
+    let pattern = find_alignment_pattern(&image, (55., 60.), 4.).unwrap();
+
+    assert_eq!(pattern.center, (49.5, 59.5));
+    assert_eq!(pattern.size, 20.);
+  }
+
+  #[test]
+  fn it_should_not_find_a_pattern_in_an_empty_image() {
+    let image = LumaBuffer::from_fn(100, 100, |_, _| 255);
+
+    assert_eq!(find_alignment_pattern(&image, (50., 50.), 4.), None);
+  }
+}
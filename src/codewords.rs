@@ -0,0 +1,122 @@
+use crate::function_patterns::FunctionPatternMap;
+use crate::modules::QRCodeModules;
+use crate::position::QRCodeVersion;
+
+/// Number of data modules left over after the last full codeword of the given version
+pub fn number_of_remainder_bits(version: &QRCodeVersion) -> usize {
+  match version.number() {
+    2..=6 => 7,
+    14..=20 | 28..=34 => 3,
+    21..=27 => 4,
+    _ => 0,
+  }
+}
+
+/// Positions of all data modules in placement order, starting at the bottom right and moving
+/// through two module wide columns alternately upwards and downwards
+pub fn data_module_positions(function_patterns: &FunctionPatternMap) -> Vec<(usize, usize)> {
+  let number_of_modules = function_patterns.number_of_modules();
+  let mut positions = vec![];
+  let mut right_column = number_of_modules - 1;
+
+  loop {
+    if right_column == 6 {
+      right_column -= 1;
+    }
+    let upwards = (right_column + 1) & 2 == 0;
+
+    for vertical in 0..number_of_modules {
+      let y = if upwards {
+        number_of_modules - 1 - vertical
+      } else {
+        vertical
+      };
+      for &x in &[right_column, right_column - 1] {
+        if !function_patterns.is_function_module(x, y) {
+          positions.push((x, y));
+        }
+      }
+    }
+
+    if right_column < 2 {
+      break;
+    }
+    right_column -= 2;
+  }
+
+  positions
+}
+
+/// Reads the (already unmasked) data modules into codewords, most significant bit first, and
+/// drops the trailing remainder bits
+pub fn codewords(modules: &QRCodeModules, function_patterns: &FunctionPatternMap) -> Vec<u8> {
+  let positions = data_module_positions(function_patterns);
+  let number_of_codewords =
+    (positions.len() - number_of_remainder_bits(function_patterns.version())) / 8;
+
+  positions
+    .chunks(8)
+    .take(number_of_codewords)
+    .map(|positions| {
+      positions.iter().fold(0u8, |codeword, &(x, y)| {
+        (codeword << 1) | modules.bits[x][y] as u8
+      })
+    })
+    .collect()
+}
+
+/// The confidence of each codeword, which is the lowest confidence of its modules, or `None`
+/// if the modules carry no confidence values
+pub fn codeword_confidence(
+  modules: &QRCodeModules,
+  function_patterns: &FunctionPatternMap,
+) -> Option<Vec<f64>> {
+  let confidence = modules.confidence.as_ref()?;
+  let positions = data_module_positions(function_patterns);
+  let number_of_codewords =
+    (positions.len() - number_of_remainder_bits(function_patterns.version())) / 8;
+
+  Some(
+    positions
+      .chunks(8)
+      .take(number_of_codewords)
+      .map(|positions| {
+        positions
+          .iter()
+          .map(|&(x, y)| confidence[x][y])
+          .fold(1., f64::min)
+      })
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_should_start_in_the_bottom_right_corner() {
+    let map = FunctionPatternMap::new(&QRCodeVersion::from_number(1));
+
+    let positions = data_module_positions(&map);
+
+    assert_eq!(
+      positions[..6],
+      [(20, 20), (19, 20), (20, 19), (19, 19), (20, 18), (19, 18)]
+    );
+    assert_eq!(positions[12 * 2..12 * 2 + 2], [(18, 9), (17, 9)]);
+    assert_eq!(positions.last(), Some(&(0, 12)));
+  }
+
+  #[test]
+  fn it_should_only_leave_remainder_bits() {
+    for number in 1..=40 {
+      let version = QRCodeVersion::from_number(number);
+      let map = FunctionPatternMap::new(&version);
+
+      let positions = data_module_positions(&map);
+
+      assert_eq!(positions.len() % 8, number_of_remainder_bits(&version));
+    }
+  }
+}
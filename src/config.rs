@@ -0,0 +1,176 @@
+use crate::modules::SamplingKernel;
+
+/// Tuning parameters of all stages of the detector. Start from one of the presets, or from
+/// [`DecoderConfig::builder`] to adjust single values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecoderConfig {
+  /// Standard deviation of the gaussian blur giving the local mean for the adaptive threshold
+  pub threshold_sigma: f32,
+  /// How much darker than the local mean a pixel has to be to be considered black
+  pub threshold_offset: u8,
+  /// Maximum relative difference between the two sides of a finder pattern scan
+  pub symmetry_threshold: f64,
+  /// Maximum relative deviation of each part of a finder pattern scan from the 1:1:3:1:1 ratios
+  pub variance_threshold: f64,
+  /// Whether finder pattern candidates are also verified along both diagonals
+  pub diagonal_cross_check: bool,
+  /// Maximum distance in pixels between neighbouring hints of the same finder pattern
+  pub cluster_eps: f64,
+  /// Minimum number of hints needed to form a finder pattern
+  pub cluster_min_points: usize,
+  /// Whether finder patterns are refined to sub-pixel precision on the grayscale image
+  pub refine_position_markers: bool,
+  /// Maximum deviation of the normalized distances between three finder patterns from those of
+  /// a square symbol
+  pub dimensions_threshold: f64,
+  /// Maximum relative deviation of the size of each of three finder patterns from their mean
+  pub marker_size_threshold: f64,
+  /// The pixels averaged to read each module
+  pub sampling_kernel: SamplingKernel,
+}
+
+impl DecoderConfig {
+  /// Reads clean, well lit codes with as little work as possible
+  pub fn fast() -> Self {
+    DecoderConfig {
+      threshold_sigma: 10.,
+      diagonal_cross_check: false,
+      cluster_min_points: 5,
+      refine_position_markers: false,
+      sampling_kernel: SamplingKernel::Pixel,
+      ..DecoderConfig::balanced()
+    }
+  }
+
+  pub fn balanced() -> Self {
+    DecoderConfig {
+      threshold_sigma: 20.,
+      threshold_offset: 0,
+      symmetry_threshold: 0.4,
+      variance_threshold: 0.5,
+      diagonal_cross_check: true,
+      cluster_eps: 4.,
+      cluster_min_points: 9,
+      refine_position_markers: true,
+      dimensions_threshold: 0.1,
+      marker_size_threshold: 0.2,
+      sampling_kernel: SamplingKernel::default(),
+    }
+  }
+
+  /// Tolerates more distortion of the finder patterns and their arrangement, which finds more
+  /// codes in blurry or skewed images at the cost of more candidates to check
+  pub fn thorough() -> Self {
+    DecoderConfig {
+      symmetry_threshold: 0.5,
+      variance_threshold: 0.6,
+      cluster_eps: 6.,
+      cluster_min_points: 5,
+      dimensions_threshold: 0.15,
+      marker_size_threshold: 0.3,
+      sampling_kernel: SamplingKernel::Disc(0.4),
+      ..DecoderConfig::balanced()
+    }
+  }
+
+  /// A builder starting from the balanced preset
+  pub fn builder() -> DecoderConfigBuilder {
+    DecoderConfigBuilder::from(DecoderConfig::balanced())
+  }
+}
+
+impl Default for DecoderConfig {
+  fn default() -> Self {
+    DecoderConfig::balanced()
+  }
+}
+
+/// Adjusts single values of a [`DecoderConfig`]. Use `DecoderConfigBuilder::from` to start from
+/// a preset other than the balanced one.
+#[derive(Debug, Clone)]
+pub struct DecoderConfigBuilder {
+  config: DecoderConfig,
+}
+
+impl From<DecoderConfig> for DecoderConfigBuilder {
+  fn from(config: DecoderConfig) -> Self {
+    DecoderConfigBuilder { config }
+  }
+}
+
+impl DecoderConfigBuilder {
+  pub fn threshold(mut self, sigma: f32, offset: u8) -> Self {
+    self.config.threshold_sigma = sigma;
+    self.config.threshold_offset = offset;
+    self
+  }
+
+  pub fn symmetry_threshold(mut self, symmetry_threshold: f64) -> Self {
+    self.config.symmetry_threshold = symmetry_threshold;
+    self
+  }
+
+  pub fn variance_threshold(mut self, variance_threshold: f64) -> Self {
+    self.config.variance_threshold = variance_threshold;
+    self
+  }
+
+  pub fn diagonal_cross_check(mut self, diagonal_cross_check: bool) -> Self {
+    self.config.diagonal_cross_check = diagonal_cross_check;
+    self
+  }
+
+  pub fn clustering(mut self, eps: f64, min_points: usize) -> Self {
+    self.config.cluster_eps = eps;
+    self.config.cluster_min_points = min_points;
+    self
+  }
+
+  pub fn refine_position_markers(mut self, refine_position_markers: bool) -> Self {
+    self.config.refine_position_markers = refine_position_markers;
+    self
+  }
+
+  pub fn dimensions_threshold(mut self, dimensions_threshold: f64) -> Self {
+    self.config.dimensions_threshold = dimensions_threshold;
+    self
+  }
+
+  pub fn marker_size_threshold(mut self, marker_size_threshold: f64) -> Self {
+    self.config.marker_size_threshold = marker_size_threshold;
+    self
+  }
+
+  pub fn sampling_kernel(mut self, sampling_kernel: SamplingKernel) -> Self {
+    self.config.sampling_kernel = sampling_kernel;
+    self
+  }
+
+  pub fn build(self) -> DecoderConfig {
+    self.config
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_should_adjust_a_preset() {
+    let config = DecoderConfigBuilder::from(DecoderConfig::fast())
+      .clustering(5., 7)
+      .sampling_kernel(SamplingKernel::Square(1))
+      .build();
+
+    assert_eq!(
+      config,
+      DecoderConfig {
+        cluster_eps: 5.,
+        cluster_min_points: 7,
+        sampling_kernel: SamplingKernel::Square(1),
+        ..DecoderConfig::fast()
+      }
+    );
+    assert_eq!(DecoderConfig::builder().build(), DecoderConfig::default());
+  }
+}
@@ -1,6 +1,46 @@
+use super::blocks::{deinterleave, deinterleave_indices, ErrorCorrectionBlocks};
+use super::codewords::{codeword_confidence, codewords};
+use super::error::QrError;
+use super::function_patterns::FunctionPatternMap;
+use super::gs1::{self, ApplicationIdentifier, Gs1Error};
 use super::modules::QRCodeModules;
 use super::position::QRCodeVersion;
-use bitvec::{bitvec, BigEndian, BitVec};
+use super::reed_solomon::{self, Correction, GaloisField};
+use super::segments::{parse_payload, Fnc1, Segment, StructuredAppend};
+
+const FORMAT_INFORMATION_MASK: u16 = 0b101_0100_0001_0010;
+const FORMAT_INFORMATION_GENERATOR: u16 = 0b101_0011_0111;
+
+const VERSION_INFORMATION_GENERATOR: u32 = 0b1_1111_0010_0101;
+/// Codewords with a module below this confidence are passed as erasures to the error correction
+const ERASURE_CONFIDENCE_THRESHOLD: f64 = 0.5;
+
+struct FormatInformationIteratorUpperLeft {
+  current_index: usize,
+}
+
+impl FormatInformationIteratorUpperLeft {
+  fn new() -> Self {
+    FormatInformationIteratorUpperLeft { current_index: 0 }
+  }
+}
+
+impl Iterator for FormatInformationIteratorUpperLeft {
+  type Item = (usize, usize);
+
+  fn next(&mut self) -> Option<(usize, usize)> {
+    let index = self.current_index;
+    self.current_index += 1;
+    match index {
+      0..=5 => Some((index, 8)),
+      6 => Some((7, 8)),
+      7 => Some((8, 8)),
+      8 => Some((8, 7)),
+      9..=14 => Some((8, 14 - index)),
+      _ => None,
+    }
+  }
+}
 
 struct FormatInformationIteratorUpperRightLowerLeft {
   current_index: usize,
@@ -20,19 +60,20 @@ impl Iterator for FormatInformationIteratorUpperRightLowerLeft {
   type Item = (usize, usize);
 
   fn next(&mut self) -> Option<(usize, usize)> {
-    if self.current_index <= 7 {
+    let index = self.current_index;
+    if index <= 6 {
       self.current_index += 1;
-      return Some((self.number_of_modules - 1 - self.current_index, 8));
+      return Some((8, self.number_of_modules - 1 - index));
     }
-    if self.current_index <= 14 {
+    if index <= 14 {
       self.current_index += 1;
-      return Some((8, self.number_of_modules - 8 + self.current_index - 8));
+      return Some((self.number_of_modules - 8 + index - 7, 8));
     }
     None
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ErrorCorrectionLevel {
   L,
   M,
@@ -49,9 +90,18 @@ impl ErrorCorrectionLevel {
       [true, false] => ErrorCorrectionLevel::H,
     }
   }
+
+  pub(crate) fn table_index(self) -> usize {
+    match self {
+      ErrorCorrectionLevel::L => 0,
+      ErrorCorrectionLevel::M => 1,
+      ErrorCorrectionLevel::Q => 2,
+      ErrorCorrectionLevel::H => 3,
+    }
+  }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mask {
   M000,
   M001,
@@ -76,25 +126,545 @@ impl Mask {
       [true, true, true] => Mask::M111,
     }
   }
+
+  /// Whether the module at `row` and `column` is inverted by this mask pattern
+  pub fn is_masked(self, row: usize, column: usize) -> bool {
+    match self {
+      Mask::M000 => (row + column).is_multiple_of(2),
+      Mask::M001 => row.is_multiple_of(2),
+      Mask::M010 => column.is_multiple_of(3),
+      Mask::M011 => (row + column).is_multiple_of(3),
+      Mask::M100 => (row / 2 + column / 3).is_multiple_of(2),
+      Mask::M101 => (row * column) % 2 + (row * column) % 3 == 0,
+      Mask::M110 => ((row * column) % 2 + (row * column) % 3).is_multiple_of(2),
+      Mask::M111 => ((row + column) % 2 + (row * column) % 3).is_multiple_of(2),
+    }
+  }
 }
 
 #[derive(Debug)]
 pub struct FormatInformation {
-  error_correction_level: ErrorCorrectionLevel,
-  mask: Mask,
+  pub error_correction_level: ErrorCorrectionLevel,
+  pub mask: Mask,
+  /// Hamming distance between the read bits and the closest valid format codeword
+  pub distance: u32,
+}
+
+fn format_information_codeword(data: u16) -> u16 {
+  let mut remainder = data << 10;
+  for bit in (10..15).rev() {
+    if remainder & (1 << bit) != 0 {
+      remainder ^= FORMAT_INFORMATION_GENERATOR << (bit - 10);
+    }
+  }
+  ((data << 10) | remainder) ^ FORMAT_INFORMATION_MASK
+}
+
+fn read_format_bits<I>(modules: &QRCodeModules, positions: I) -> u16
+where
+  I: Iterator<Item = (usize, usize)>,
+{
+  positions.fold(0, |bits, (x, y)| (bits << 1) | modules.bits[x][y] as u16)
+}
+
+fn closest_format_data(bits: u16) -> (u16, u32) {
+  (0..32)
+    .map(|data| {
+      (
+        data,
+        (format_information_codeword(data) ^ bits).count_ones(),
+      )
+    })
+    .min_by_key(|(_, distance)| *distance)
+    .unwrap()
 }
 
-pub fn decode_format_information(modules: &QRCodeModules) -> FormatInformation {
-  let format_mask = bitvec![1, 0, 1, 0, 1, 0, 0, 0, 0, 0, 1, 0, 0, 1, 0];
-  let format_iterator1 = FormatInformationIteratorUpperRightLowerLeft::new(&modules.version);
-  let mut format_vec: BitVec = format_iterator1.map(|(x, y)| modules.bits[x][y]).collect();
-  format_vec ^= format_mask.iter();
+/// Reads both copies of the format information and corrects the one closer to a valid codeword.
+/// Fails if neither is within the correction capacity of the BCH code.
+pub fn decode_format_information(modules: &QRCodeModules) -> Result<FormatInformation, QrError> {
+  let upper_left = read_format_bits(modules, FormatInformationIteratorUpperLeft::new());
+  let upper_right_lower_left = read_format_bits(
+    modules,
+    FormatInformationIteratorUpperRightLowerLeft::new(&modules.version),
+  );
+  let (data, distance) = [upper_left, upper_right_lower_left]
+    .iter()
+    .map(|&bits| closest_format_data(bits))
+    .min_by_key(|(_, distance)| *distance)
+    .unwrap();
 
-  let error_correction_level_bits: [bool; 2] = [format_vec[0], format_vec[1]];
-  let mask_bits: [bool; 3] = [format_vec[2], format_vec[3], format_vec[4]];
+  if distance > 3 {
+    return Err(QrError::FormatUnrecoverable);
+  }
 
-  FormatInformation {
+  let bit = |index: u16| data & (1 << index) != 0;
+  let error_correction_level_bits: [bool; 2] = [bit(4), bit(3)];
+  let mask_bits: [bool; 3] = [bit(2), bit(1), bit(0)];
+
+  Ok(FormatInformation {
     error_correction_level: ErrorCorrectionLevel::from_bits(error_correction_level_bits),
     mask: Mask::from_bits(mask_bits),
+    distance,
+  })
+}
+
+#[derive(Debug)]
+pub struct VersionInformation {
+  pub version: QRCodeVersion,
+  /// Hamming distance between the read bits and the closest valid version codeword
+  pub distance: u32,
+  /// Whether both blocks were corrected to the same version
+  pub copies_agree: bool,
+}
+
+fn version_information_codeword(version: u32) -> u32 {
+  let mut remainder = version << 12;
+  for bit in (12..18).rev() {
+    if remainder & (1 << bit) != 0 {
+      remainder ^= VERSION_INFORMATION_GENERATOR << (bit - 12);
+    }
+  }
+  (version << 12) | remainder
+}
+
+pub(crate) fn version_information_positions(number_of_modules: usize) -> [Vec<(usize, usize)>; 2] {
+  let mut upper_right = Vec::with_capacity(18);
+  let mut lower_left = Vec::with_capacity(18);
+  for i in (0..6).rev() {
+    for j in (number_of_modules - 11..number_of_modules - 8).rev() {
+      upper_right.push((j, i));
+      lower_left.push((i, j));
+    }
+  }
+  [upper_right, lower_left]
+}
+
+/// Reads both version information blocks of a version 7+ code and returns the closest valid
+/// version, or `None` for smaller versions, which carry no version information. Fails if neither
+/// block is within the correction capacity of the Golay code.
+pub fn decode_version_information(
+  modules: &QRCodeModules,
+) -> Result<Option<VersionInformation>, QrError> {
+  let number_of_modules = modules.version.number_of_modules() as usize;
+  if modules.version.number() < 7 {
+    return Ok(None);
+  }
+
+  let closest_versions: Vec<(u32, u32)> = version_information_positions(number_of_modules)
+    .iter()
+    .map(|positions| {
+      let bits = positions.iter().fold(0u32, |bits, &(x, y)| {
+        (bits << 1) | modules.bits[x][y] as u32
+      });
+      (7..=40)
+        .map(|version| {
+          (
+            version,
+            (version_information_codeword(version) ^ bits).count_ones(),
+          )
+        })
+        .min_by_key(|(_, distance)| *distance)
+        .unwrap()
+    })
+    .collect();
+  let copies_agree = closest_versions[0].0 == closest_versions[1].0;
+  let (version, distance) = closest_versions
+    .into_iter()
+    .min_by_key(|(_, distance)| *distance)
+    .unwrap();
+
+  if distance > 3 {
+    return Err(QrError::VersionUnrecoverable);
+  }
+
+  Ok(Some(VersionInformation {
+    version: QRCodeVersion::from_number(version),
+    distance,
+    copies_agree,
+  }))
+}
+
+#[derive(Debug)]
+pub struct DecodedQRCode {
+  pub version: QRCodeVersion,
+  pub error_correction_level: ErrorCorrectionLevel,
+  pub mask: Mask,
+  pub segments: Vec<Segment>,
+  pub structured_append: Option<StructuredAppend>,
+  pub fnc1: Option<Fnc1>,
+  pub data: Vec<u8>,
+  pub text: String,
+  pub corrections: Vec<Correction>,
+  /// Hamming distance between the read format information and the closest valid codeword
+  pub format_information_distance: u32,
+  /// Image coordinates of the top left, top right, bottom right and bottom left corner of the
+  /// symbol, only available for codes read from an image
+  pub corners: Option<[(f64, f64); 4]>,
+}
+
+impl DecodedQRCode {
+  /// Splits the text of a GS1 QR code (FNC1 in first position) into its Application
+  /// Identifiers, returns `None` for all other codes
+  pub fn application_identifiers(&self) -> Option<Result<Vec<ApplicationIdentifier>, Gs1Error>> {
+    match self.fnc1 {
+      Some(Fnc1::FirstPosition) => Some(gs1::parse_element_string(&self.text)),
+      _ => None,
+    }
+  }
+}
+
+/// Removes the data mask from all modules that are not part of a function pattern
+pub fn unmask(modules: &mut QRCodeModules, mask: &Mask) {
+  let number_of_modules = modules.version.number_of_modules() as usize;
+  let function_patterns = FunctionPatternMap::for_version(&modules.version);
+
+  for x in 0..number_of_modules {
+    for y in 0..number_of_modules {
+      if mask.is_masked(y, x) && !function_patterns.is_function_module(x, y) {
+        let bit = modules.bits[x][y];
+        modules.bits[x].set(y, !bit);
+      }
+    }
+  }
+}
+
+/// Indices of the codewords containing modules with a confidence below
+/// `ERASURE_CONFIDENCE_THRESHOLD`, least confident first
+fn unreliable_codewords(
+  modules: &QRCodeModules,
+  function_patterns: &FunctionPatternMap,
+) -> Vec<usize> {
+  let confidence = match codeword_confidence(modules, function_patterns) {
+    Some(confidence) => confidence,
+    None => return vec![],
+  };
+  let mut unreliable: Vec<usize> = (0..confidence.len())
+    .filter(|&index| confidence[index] < ERASURE_CONFIDENCE_THRESHOLD)
+    .collect();
+  unreliable.sort_by(|&a, &b| confidence[a].partial_cmp(&confidence[b]).unwrap());
+  unreliable
+}
+
+pub fn decode_payload(modules: &QRCodeModules) -> Result<DecodedQRCode, QrError> {
+  let version = modules.version.clone();
+  if version.number() < 1 || version.number() > 40 {
+    return Err(QrError::UnsupportedVersion(version.number()));
+  }
+
+  let format_information = decode_format_information(modules)?;
+  let mut unmasked = modules.clone();
+  unmask(&mut unmasked, &format_information.mask);
+  let function_patterns = FunctionPatternMap::for_version(&unmasked.version);
+  let codewords = codewords(&unmasked, &function_patterns);
+  let error_correction_blocks =
+    ErrorCorrectionBlocks::new(&version, format_information.error_correction_level);
+  let erasures = deinterleave_indices(
+    &unreliable_codewords(&unmasked, &function_patterns),
+    &error_correction_blocks,
+  );
+  let blocks = deinterleave(&codewords, &error_correction_blocks);
+
+  let field = GaloisField::new();
+  let mut corrections = Vec::with_capacity(blocks.len());
+  let mut data_codewords = vec![];
+  for (index, (mut block, mut erasures)) in blocks.into_iter().zip(erasures).enumerate() {
+    let number_of_ec_codewords = block.number_of_ec_codewords();
+    erasures.truncate(number_of_ec_codewords);
+    let mut corrected = block.codewords.clone();
+    let correction =
+      reed_solomon::correct(&field, &mut corrected, number_of_ec_codewords, &erasures)
+        .or_else(|_| {
+          // Erasures flagged wrongly use up twice the capacity they would as errors
+          corrected = block.codewords.clone();
+          reed_solomon::correct(&field, &mut corrected, number_of_ec_codewords, &[])
+        })
+        .map_err(|_| QrError::TooManyErrors { block: index })?;
+    block.codewords = corrected;
+    corrections.push(correction);
+    data_codewords.extend_from_slice(block.data());
+  }
+
+  let payload = parse_payload(&data_codewords, &version)?;
+  let segments = payload.segments;
+  let data = segments
+    .iter()
+    .flat_map(|segment| segment.data.iter().cloned())
+    .collect();
+  let text = segments
+    .iter()
+    .map(|segment| segment.text.as_str())
+    .collect();
+
+  Ok(DecodedQRCode {
+    version,
+    error_correction_level: format_information.error_correction_level,
+    mask: format_information.mask,
+    segments,
+    structured_append: payload.structured_append,
+    fnc1: payload.fnc1,
+    data,
+    text,
+    corrections,
+    format_information_distance: format_information.distance,
+    corners: None,
+  })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+  use crate::codewords::data_module_positions;
+  use crate::function_patterns::alignment_pattern_positions;
+  use crate::luma::LumaBuffer;
+  use bitvec::BitVec;
+
+  /// Data and error correction codewords of a version 1-M code holding "01234567", to be placed
+  /// with format bits "101111001111100" and mask 010
+  pub(crate) const NUMERIC_VERSION_1_CODEWORDS: [u8; 26] = [
+    0x10, 0x20, 0x0c, 0x56, 0x61, 0x80, 0xec, 0x11, 0xec, 0x11, 0xec, 0x11, 0xec, 0x11, 0xec, 0x11,
+    0xa5, 0x24, 0xd4, 0xc1, 0xed, 0x36, 0xc7, 0x87, 0x2c, 0x55,
+  ];
+
+  pub(crate) fn modules_from_codewords(
+    version_number: u32,
+    format_bits: &str,
+    mask: Mask,
+    codewords: &[u8],
+  ) -> QRCodeModules {
+    let version =
+      QRCodeVersion::from_estimated_number_of_modules(f64::from(4 * version_number + 17));
+    let number_of_modules = version.number_of_modules() as usize;
+    let mut bits: Vec<BitVec> = (0..number_of_modules)
+      .map(|_| {
+        let mut bits_x = BitVec::with_capacity(number_of_modules);
+        for _ in 0..number_of_modules {
+          bits_x.push(false);
+        }
+        bits_x
+      })
+      .collect();
+
+    let format_positions = FormatInformationIteratorUpperLeft::new()
+      .chain(FormatInformationIteratorUpperRightLowerLeft::new(&version));
+    for ((x, y), bit) in format_positions.zip(format_bits.chars().cycle()) {
+      bits[x].set(y, bit == '1');
+    }
+    let data_positions = data_module_positions(&FunctionPatternMap::new(&version));
+    for (index, &(x, y)) in data_positions.iter().enumerate() {
+      let bit = codewords
+        .get(index / 8)
+        .map_or(false, |codeword| (codeword >> (7 - index % 8)) & 1 == 1);
+      bits[x].set(y, bit ^ mask.is_masked(y, x));
+    }
+
+    QRCodeModules {
+      version,
+      bits,
+      confidence: None,
+    }
+  }
+
+  /// Whether the module at the given column and row, counted from the top left corner of a
+  /// finder pattern, is a dark module of it
+  pub(crate) fn is_dark_finder_pattern_module(x: i64, y: i64) -> bool {
+    let ring = i64::max((x - 3).abs(), (y - 3).abs());
+    ring <= 3 && ring != 2
+  }
+
+  /// Adds the finder, timing and alignment patterns, the version information and the dark
+  /// module to modules holding only format information and data
+  pub(crate) fn add_function_patterns(modules: &mut QRCodeModules) {
+    let number_of_modules = modules.version.number_of_modules() as usize;
+    let last_finder_pattern = number_of_modules - 7;
+    for &(left, top) in &[(0, 0), (last_finder_pattern, 0), (0, last_finder_pattern)] {
+      for x in 0..7 {
+        for y in 0..7 {
+          let dark = is_dark_finder_pattern_module(x as i64, y as i64);
+          modules.bits[left + x].set(top + y, dark);
+        }
+      }
+    }
+    for index in 8..number_of_modules - 8 {
+      modules.bits[index].set(6, index.is_multiple_of(2));
+      modules.bits[6].set(index, index.is_multiple_of(2));
+    }
+    for (center_x, center_y) in alignment_pattern_positions(&modules.version) {
+      for x in center_x - 2..=center_x + 2 {
+        for y in center_y - 2..=center_y + 2 {
+          let ring = usize::max(x.abs_diff(center_x), y.abs_diff(center_y));
+          modules.bits[x].set(y, ring != 1);
+        }
+      }
+    }
+    if modules.version.number() >= 7 {
+      let codeword = version_information_codeword(modules.version.number());
+      for positions in version_information_positions(number_of_modules).iter() {
+        for (index, &(x, y)) in positions.iter().enumerate() {
+          modules.bits[x].set(y, (codeword >> (17 - index)) & 1 == 1);
+        }
+      }
+    }
+    modules.bits[8].set(number_of_modules - 8, true);
+  }
+
+  /// Renders the modules dark on light, with `to_modules` mapping the center of each pixel to
+  /// module coordinates. Everything outside of the symbol is light.
+  pub(crate) fn render_modules_through<F>(
+    modules: &QRCodeModules,
+    width: u32,
+    height: u32,
+    to_modules: F,
+  ) -> LumaBuffer
+  where
+    F: Fn((f64, f64)) -> (f64, f64),
+  {
+    let number_of_modules = f64::from(modules.version.number_of_modules());
+    LumaBuffer::from_fn(width, height, |x, y| {
+      let (x, y) = to_modules((f64::from(x), f64::from(y)));
+      let inside = (0. ..number_of_modules).contains(&x) && (0. ..number_of_modules).contains(&y);
+      if inside && modules.bits[x as usize][y as usize] {
+        0
+      } else {
+        255
+      }
+    })
+  }
+
+  /// Renders the modules upright, each `module_size` pixels wide, surrounded by a quiet zone of
+  /// `quiet_zone` modules
+  pub(crate) fn render_modules(
+    modules: &QRCodeModules,
+    module_size: u32,
+    quiet_zone: u32,
+  ) -> LumaBuffer {
+    let size = (modules.version.number_of_modules() + 2 * quiet_zone) * module_size;
+    let (module_size, quiet_zone) = (f64::from(module_size), f64::from(quiet_zone));
+    render_modules_through(modules, size, size, |(x, y)| {
+      (
+        (x / module_size - quiet_zone).floor(),
+        (y / module_size - quiet_zone).floor(),
+      )
+    })
+  }
+
+  #[test]
+  fn it_should_decode_a_numeric_version_1_code() {
+    let codewords = NUMERIC_VERSION_1_CODEWORDS;
+    let modules = modules_from_codewords(1, "101111001111100", Mask::M010, &codewords);
+
+    let decoded = decode_payload(&modules).unwrap();
+
+    assert_eq!(decoded.error_correction_level, ErrorCorrectionLevel::M);
+    assert_eq!(decoded.mask, Mask::M010);
+    assert_eq!(decoded.text, "01234567");
+  }
+
+  #[test]
+  fn it_should_correct_damaged_codewords() {
+    let mut codewords = NUMERIC_VERSION_1_CODEWORDS;
+    codewords[1] ^= 0xff;
+    codewords[4] ^= 0x10;
+    codewords[20] ^= 0x01;
+    let modules = modules_from_codewords(1, "101111001111100", Mask::M010, &codewords);
+
+    let decoded = decode_payload(&modules).unwrap();
+
+    assert_eq!(decoded.text, "01234567");
+    assert_eq!(decoded.corrections[0].errors, 3);
+  }
+
+  #[test]
+  fn it_should_pass_low_confidence_codewords_as_erasures() {
+    let mut codewords = NUMERIC_VERSION_1_CODEWORDS;
+    let damaged = [1, 4, 7, 12, 18, 20, 23];
+    for &index in &damaged {
+      codewords[index] ^= 0x5a;
+    }
+    let mut modules = modules_from_codewords(1, "101111001111100", Mask::M010, &codewords);
+    let mut confidence = vec![vec![1.; 21]; 21];
+    let positions = data_module_positions(&FunctionPatternMap::new(&modules.version));
+    for &index in &damaged {
+      let (x, y) = positions[index * 8];
+      confidence[x][y] = 0.1;
+    }
+
+    assert_eq!(
+      decode_payload(&modules).err(),
+      Some(QrError::TooManyErrors { block: 0 })
+    );
+    modules.confidence = Some(confidence);
+    let decoded = decode_payload(&modules).unwrap();
+
+    assert_eq!(decoded.text, "01234567");
+    assert_eq!(decoded.corrections[0].erasures, 7);
+  }
+
+  #[test]
+  fn it_should_recover_format_information_from_damaged_copies() {
+    let codewords = [0; 26];
+    let mut modules = modules_from_codewords(1, "101111001111100", Mask::M010, &codewords);
+    for &(x, y) in &[(0, 8), (2, 8), (8, 4), (8, 20), (15, 8)] {
+      let bit = modules.bits[x][y];
+      modules.bits[x].set(y, !bit);
+    }
+
+    let format_information = decode_format_information(&modules).unwrap();
+
+    assert_eq!(
+      format_information.error_correction_level,
+      ErrorCorrectionLevel::M
+    );
+    assert_eq!(format_information.mask, Mask::M010);
+    assert_eq!(format_information.distance, 2);
+  }
+
+  #[test]
+  fn it_should_decode_damaged_version_information() {
+    let version = QRCodeVersion::from_number(7);
+    let mut modules = modules_from_codewords(7, "101111001111100", Mask::M010, &[]);
+    let [upper_right, lower_left] = version_information_positions(45);
+    for (index, &(x, y)) in upper_right.iter().chain(lower_left.iter()).enumerate() {
+      let bit = (0b00_0111_1100_1001_0100 >> (17 - index % 18)) & 1 == 1;
+      let damaged = [0, 5, 9, 18, 20].contains(&index);
+      modules.bits[x].set(y, bit ^ damaged);
+    }
+
+    let version_information = decode_version_information(&modules).unwrap().unwrap();
+
+    assert_eq!(version_information.version, version);
+    assert_eq!(version_information.distance, 2);
+    assert!(version_information.copies_agree);
+  }
+
+  #[test]
+  fn it_should_fail_on_version_information_beyond_correction() {
+    let mut modules = modules_from_codewords(7, "101111001111100", Mask::M010, &[]);
+    let [upper_right, lower_left] = version_information_positions(45);
+    for (index, &(x, y)) in upper_right.iter().chain(lower_left.iter()).enumerate() {
+      let bit = (0b00_0111_1100_1001_0100 >> (17 - index % 18)) & 1 == 1;
+      modules.bits[x].set(y, bit ^ (index % 18 < 4));
+    }
+
+    assert_eq!(
+      decode_version_information(&modules).map(|information| information.is_some()),
+      Err(QrError::VersionUnrecoverable)
+    );
+  }
+
+  #[test]
+  fn it_should_only_unmask_data_modules() {
+    let mut modules = modules_from_codewords(2, "000000000000000", Mask::M000, &[]);
+    modules.bits[6].set(10, true);
+
+    unmask(&mut modules, &Mask::M000);
+
+    let number_of_dark_modules = modules
+      .bits
+      .iter()
+      .flat_map(|column| column.iter())
+      .filter(|bit| *bit)
+      .count();
+    assert!(modules.bits[6][10]);
+    assert_eq!(number_of_dark_modules, 1);
   }
 }
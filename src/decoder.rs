@@ -0,0 +1,243 @@
+use crate::config::DecoderConfig;
+use crate::decode::{decode_payload, DecodedQRCode};
+use crate::error::QrError;
+use crate::luma::{LumaImage, LumaView};
+use crate::marker_refinement::refine_position_markers;
+use crate::modules::read_modules_for_all_codes;
+use crate::position::find_estimated_qr_code_positions;
+use crate::position_markers::{
+  cluster_position_marker_hints, detect_position_marker_hints_with_statistics, HintStatistics,
+};
+use crate::threshold::{detector_threshold_into, ThresholdBuffers};
+#[cfg(feature = "image")]
+use image::{imageops::colorops, DynamicImage};
+
+/// What happened during the last call to one of the decode methods of a [`Decoder`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+  pub hint_statistics: HintStatistics,
+  pub number_of_position_markers: usize,
+  /// Number of finder pattern triples that were read as a code
+  pub number_of_candidates: usize,
+  /// Why no candidates were found, or why each candidate that did not yield a code failed
+  pub failures: Vec<QrError>,
+}
+
+/// Runs the whole pipeline, from the grayscale image to the decoded payloads. A decoder keeps
+/// the buffers of the threshold between images, so decoding a stream of frames with the same
+/// decoder does not allocate them for every frame.
+#[derive(Debug, Default)]
+pub struct Decoder {
+  config: DecoderConfig,
+  diagnostics: Diagnostics,
+  buffers: ThresholdBuffers,
+}
+
+impl Decoder {
+  pub fn new(config: DecoderConfig) -> Self {
+    Decoder {
+      config,
+      diagnostics: Diagnostics::default(),
+      buffers: ThresholdBuffers::default(),
+    }
+  }
+
+  pub fn config(&self) -> &DecoderConfig {
+    &self.config
+  }
+
+  pub fn diagnostics(&self) -> &Diagnostics {
+    &self.diagnostics
+  }
+
+  /// Decodes all codes in an image of any color type
+  #[cfg(feature = "image")]
+  pub fn decode(&mut self, image: &DynamicImage) -> Vec<DecodedQRCode> {
+    let grayscale = colorops::grayscale(image);
+    self.decode_grayscale(&grayscale)
+  }
+
+  /// Decodes all codes in a row major buffer of 8 bit luma values, e.g. the Y plane of a camera
+  /// frame. Rows start `stride` bytes apart. Fails with [`QrError::InvalidBuffer`] if the buffer
+  /// does not fit the dimensions.
+  pub fn decode_luma(
+    &mut self,
+    data: &[u8],
+    width: u32,
+    height: u32,
+    stride: usize,
+  ) -> Result<Vec<DecodedQRCode>, QrError> {
+    let view = LumaView::new(data, width, height, stride).ok_or(QrError::InvalidBuffer)?;
+    Ok(self.decode_grayscale(&view))
+  }
+
+  /// Decodes all codes in any grayscale image, like a borrowed [`LumaView`]
+  pub fn decode_grayscale<I: LumaImage>(&mut self, grayscale: &I) -> Vec<DecodedQRCode> {
+    let config = &self.config;
+    detector_threshold_into(grayscale, config, &mut self.buffers);
+    let thresholded = self.buffers.thresholded();
+    let (hints, hint_statistics) =
+      detect_position_marker_hints_with_statistics(thresholded, config);
+    let markers = cluster_position_marker_hints(&hints, config);
+    let markers = if config.refine_position_markers {
+      refine_position_markers(grayscale, &markers)
+    } else {
+      markers
+    };
+    let mut diagnostics = Diagnostics {
+      hint_statistics,
+      number_of_position_markers: markers.len(),
+      number_of_candidates: 0,
+      failures: vec![],
+    };
+    let positions = match find_estimated_qr_code_positions(thresholded, &markers, config) {
+      Ok(positions) => positions,
+      Err(error) => {
+        diagnostics.failures.push(error);
+        self.diagnostics = diagnostics;
+        return vec![];
+      }
+    };
+    diagnostics.number_of_candidates = positions.len();
+    let codes = read_modules_for_all_codes(grayscale, &self.buffers, &positions, config);
+
+    let mut decoded = vec![];
+    for (position, modules) in positions.iter().zip(codes.iter()) {
+      let modules = match modules {
+        Ok(modules) => modules,
+        Err(error) => {
+          diagnostics.failures.push(error.clone());
+          continue;
+        }
+      };
+      match decode_payload(modules) {
+        Ok(mut code) => {
+          let n = f64::from(modules.version.number_of_modules());
+          code.corners = position
+            .transform_for_version(&modules.version)
+            .ok()
+            .map(|transform| {
+              [
+                transform.map((0., 0.)),
+                transform.map((n, 0.)),
+                transform.map((n, n)),
+                transform.map((0., n)),
+              ]
+            });
+          decoded.push(code);
+        }
+        Err(error) => diagnostics.failures.push(error),
+      }
+    }
+    self.diagnostics = diagnostics;
+    decoded
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::decode::tests::{
+    add_function_patterns, is_dark_finder_pattern_module, modules_from_codewords, render_modules,
+    NUMERIC_VERSION_1_CODEWORDS,
+  };
+  use crate::decode::Mask;
+  use crate::luma::LumaBuffer;
+
+  const MODULE_SIZE: u32 = 4;
+  const QUIET_ZONE: u32 = 4;
+
+  /// Renders the version 1 code of the decode tests, with its function patterns added
+  fn render_code() -> LumaBuffer {
+    let mut modules = modules_from_codewords(
+      1,
+      "101111001111100",
+      Mask::M010,
+      &NUMERIC_VERSION_1_CODEWORDS,
+    );
+    add_function_patterns(&mut modules);
+    render_modules(&modules, MODULE_SIZE, QUIET_ZONE)
+  }
+
+  #[test]
+  fn it_should_decode_a_code_with_its_corners() {
+    let image = render_code();
+    let mut decoder = Decoder::default();
+
+    let codes = decoder.decode_grayscale(&image);
+
+    assert_eq!(codes.len(), 1);
+    assert_eq!(codes[0].text, "01234567");
+    let start = f64::from(QUIET_ZONE * MODULE_SIZE);
+    let end = f64::from((21 + QUIET_ZONE) * MODULE_SIZE);
+    let expected = [(start, start), (end, start), (end, end), (start, end)];
+    for (corner, expected) in codes[0].corners.unwrap().iter().zip(expected.iter()) {
+      assert!(crate::math::euclidean_distance(*corner, *expected) < 1.5);
+    }
+    assert_eq!(decoder.diagnostics().number_of_position_markers, 3);
+    assert!(decoder.diagnostics().failures.is_empty());
+  }
+
+  #[test]
+  fn it_should_report_why_no_code_was_found() {
+    let image = LumaBuffer::from_fn(50, 50, |_, _| 255);
+    let mut decoder = Decoder::default();
+
+    assert!(decoder.decode_grayscale(&image).is_empty());
+    assert_eq!(
+      decoder.diagnostics().failures,
+      vec![QrError::NoFinderPatterns { found: 0 }]
+    );
+  }
+
+  #[test]
+  fn it_should_decode_a_luma_buffer_with_padded_rows() {
+    let image = render_code();
+    let (width, height) = (image.width(), image.height());
+    let stride = width as usize + 12;
+    let mut data = vec![0; stride * height as usize];
+    for y in 0..height {
+      for x in 0..width {
+        data[y as usize * stride + x as usize] = image.luma(x, y);
+      }
+    }
+    let mut decoder = Decoder::new(DecoderConfig::fast());
+
+    let codes = decoder.decode_luma(&data, width, height, stride).unwrap();
+
+    assert_eq!(codes.len(), 1);
+    assert_eq!(codes[0].text, "01234567");
+    assert_eq!(
+      decoder
+        .decode_luma(&data[..data.len() - 13], width, height, stride)
+        .unwrap_err(),
+      QrError::InvalidBuffer
+    );
+  }
+
+  #[test]
+  fn it_should_reject_finder_patterns_too_far_apart_for_any_version() {
+    const MODULE_SIZE: u32 = 2;
+    let finder_patterns = [(4, 4), (184, 4), (4, 184)];
+    let size = 195 * MODULE_SIZE;
+    let image = LumaBuffer::from_fn(size, size, |x, y| {
+      let (x, y) = (i64::from(x / MODULE_SIZE), i64::from(y / MODULE_SIZE));
+      let dark = finder_patterns
+        .iter()
+        .any(|&(left, top)| is_dark_finder_pattern_module(x - left, y - top));
+      if dark {
+        0
+      } else {
+        255
+      }
+    });
+    let mut decoder = Decoder::default();
+
+    assert!(decoder.decode_grayscale(&image).is_empty());
+    assert_eq!(decoder.diagnostics().number_of_position_markers, 3);
+    assert_eq!(
+      decoder.diagnostics().failures,
+      vec![QrError::UnsupportedVersion(43)]
+    );
+  }
+}
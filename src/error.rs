@@ -0,0 +1,85 @@
+use crate::segments::SegmentError;
+use std::error::Error;
+use std::fmt;
+
+/// Why a stage of the pipeline could not read a code
+#[derive(Debug, Clone, PartialEq)]
+pub enum QrError {
+  /// The buffer passed as an image is too short for its dimensions, or its rows are closer
+  /// together than its width
+  InvalidBuffer,
+  /// Fewer than the three finder patterns of a code were found
+  NoFinderPatterns { found: usize },
+  /// Enough finder patterns were found, but no three of them are arranged like the corners of a
+  /// code
+  AmbiguousTriple,
+  /// The version information disagrees with the version estimated from the finder patterns, and
+  /// sampling the symbol with the version it encodes does not confirm that version
+  VersionMismatch { estimated: u32, read: u32 },
+  /// Both copies of the version information are too damaged to be corrected
+  VersionUnrecoverable,
+  /// The reference points of the symbol do not determine a transform onto the image
+  DegenerateTransform,
+  /// Parts of the symbol lie outside of the image
+  OutOfImage,
+  /// The version is outside of the range 1 to 40 defined by the standard
+  UnsupportedVersion(u32),
+  /// Both copies of the format information are too damaged to be corrected
+  FormatUnrecoverable,
+  /// The error correction could not restore the given block
+  TooManyErrors { block: usize },
+  /// The corrected data codewords are not a valid sequence of segments
+  InvalidSegment(SegmentError),
+}
+
+impl fmt::Display for QrError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      QrError::InvalidBuffer => write!(f, "the buffer does not fit the image dimensions"),
+      QrError::NoFinderPatterns { found } => {
+        write!(f, "found {} finder patterns, a code needs three", found)
+      }
+      QrError::AmbiguousTriple => write!(f, "no three finder patterns are arranged like a code"),
+      QrError::VersionMismatch { estimated, read } => write!(
+        f,
+        "version {} was estimated, but version {} could not be confirmed",
+        estimated, read
+      ),
+      QrError::VersionUnrecoverable => write!(f, "the version information is unrecoverable"),
+      QrError::DegenerateTransform => write!(f, "the symbol cannot be mapped onto the image"),
+      QrError::OutOfImage => write!(f, "the symbol extends beyond the image"),
+      QrError::UnsupportedVersion(version) => write!(f, "version {} is not supported", version),
+      QrError::FormatUnrecoverable => write!(f, "the format information is unrecoverable"),
+      QrError::TooManyErrors { block } => write!(f, "too many errors in block {}", block),
+      QrError::InvalidSegment(error) => write!(f, "invalid segment: {}", error),
+    }
+  }
+}
+
+impl Error for QrError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      QrError::InvalidSegment(error) => Some(error),
+      _ => None,
+    }
+  }
+}
+
+impl From<SegmentError> for QrError {
+  fn from(error: SegmentError) -> Self {
+    QrError::InvalidSegment(error)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_should_describe_errors_with_their_source() {
+    let error: Box<dyn Error> = Box::new(QrError::from(SegmentError::InvalidCharacter));
+
+    assert_eq!(error.to_string(), "invalid segment: invalid character");
+    assert_eq!(error.source().unwrap().to_string(), "invalid character");
+  }
+}
@@ -0,0 +1,215 @@
+use crate::position::QRCodeVersion;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const ALIGNMENT_PATTERN_CENTERS: [&[usize]; 40] = [
+  &[],
+  &[6, 18],
+  &[6, 22],
+  &[6, 26],
+  &[6, 30],
+  &[6, 34],
+  &[6, 22, 38],
+  &[6, 24, 42],
+  &[6, 26, 46],
+  &[6, 28, 50],
+  &[6, 30, 54],
+  &[6, 32, 58],
+  &[6, 34, 62],
+  &[6, 26, 46, 66],
+  &[6, 26, 48, 70],
+  &[6, 26, 50, 74],
+  &[6, 30, 54, 78],
+  &[6, 30, 56, 82],
+  &[6, 30, 58, 86],
+  &[6, 34, 62, 90],
+  &[6, 28, 50, 72, 94],
+  &[6, 26, 50, 74, 98],
+  &[6, 30, 54, 78, 102],
+  &[6, 28, 54, 80, 106],
+  &[6, 32, 58, 84, 110],
+  &[6, 30, 58, 86, 114],
+  &[6, 34, 62, 90, 118],
+  &[6, 26, 50, 74, 98, 122],
+  &[6, 30, 54, 78, 102, 126],
+  &[6, 26, 52, 78, 104, 130],
+  &[6, 30, 56, 82, 108, 134],
+  &[6, 34, 60, 86, 112, 138],
+  &[6, 30, 58, 86, 114, 142],
+  &[6, 34, 62, 90, 118, 146],
+  &[6, 30, 54, 78, 102, 126, 150],
+  &[6, 24, 50, 76, 102, 128, 154],
+  &[6, 28, 54, 80, 106, 132, 158],
+  &[6, 32, 58, 84, 110, 136, 162],
+  &[6, 26, 54, 82, 110, 138, 166],
+  &[6, 30, 58, 86, 114, 142, 170],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FunctionPattern {
+  FinderPattern,
+  Separator,
+  TimingPattern,
+  AlignmentPattern,
+  FormatInformation,
+  VersionInformation,
+  DarkModule,
+}
+
+/// Alignment pattern center coordinates for the given version, used for both the row and the
+/// column of each pattern, or `None` for versions outside 1 to 40
+pub fn alignment_pattern_centers(version: &QRCodeVersion) -> Option<&'static [usize]> {
+  let index = (version.number() as usize).checked_sub(1)?;
+  ALIGNMENT_PATTERN_CENTERS.get(index).cloned()
+}
+
+/// All alignment pattern centers of the given version that do not overlap a finder pattern, none
+/// for versions outside 1 to 40
+pub fn alignment_pattern_positions(version: &QRCodeVersion) -> Vec<(usize, usize)> {
+  let centers = alignment_pattern_centers(version).unwrap_or(&[]);
+  let last_center = centers.last().cloned().unwrap_or(0);
+
+  centers
+    .iter()
+    .flat_map(|&x| centers.iter().map(move |&y| (x, y)))
+    .filter(|position| ![(6, 6), (6, last_center), (last_center, 6)].contains(position))
+    .collect()
+}
+
+#[derive(Debug)]
+pub struct FunctionPatternMap {
+  version: QRCodeVersion,
+  number_of_modules: usize,
+  patterns: Vec<Option<FunctionPattern>>,
+}
+
+thread_local! {
+  static FUNCTION_PATTERN_MAPS: RefCell<HashMap<u32, Rc<FunctionPatternMap>>> =
+    RefCell::new(HashMap::new());
+}
+
+impl FunctionPatternMap {
+  pub fn new(version: &QRCodeVersion) -> Self {
+    let number_of_modules = version.number_of_modules() as usize;
+    let mut map = FunctionPatternMap {
+      version: version.clone(),
+      number_of_modules,
+      patterns: vec![None; number_of_modules * number_of_modules],
+    };
+    let last = number_of_modules - 1;
+
+    for &(offset_x, offset_y) in &[(0, 0), (last - 6, 0), (0, last - 6)] {
+      map.fill(offset_x, offset_y, 7, 7, FunctionPattern::FinderPattern);
+    }
+    for i in 0..8 {
+      map.set(7, i, FunctionPattern::Separator);
+      map.set(i, 7, FunctionPattern::Separator);
+      map.set(last - 7, i, FunctionPattern::Separator);
+      map.set(last - i, 7, FunctionPattern::Separator);
+      map.set(7, last - i, FunctionPattern::Separator);
+      map.set(i, last - 7, FunctionPattern::Separator);
+    }
+    for i in 8..number_of_modules - 8 {
+      map.set(i, 6, FunctionPattern::TimingPattern);
+      map.set(6, i, FunctionPattern::TimingPattern);
+    }
+    for (x, y) in alignment_pattern_positions(version) {
+      map.fill(x - 2, y - 2, 5, 5, FunctionPattern::AlignmentPattern);
+    }
+    for i in (0..9).filter(|&i| i != 6) {
+      map.set(i, 8, FunctionPattern::FormatInformation);
+      map.set(8, i, FunctionPattern::FormatInformation);
+    }
+    for i in 0..8 {
+      map.set(last - i, 8, FunctionPattern::FormatInformation);
+      map.set(8, last - i, FunctionPattern::FormatInformation);
+    }
+    map.set(8, last - 7, FunctionPattern::DarkModule);
+    if version.number() >= 7 {
+      map.fill(last - 10, 0, 3, 6, FunctionPattern::VersionInformation);
+      map.fill(0, last - 10, 6, 3, FunctionPattern::VersionInformation);
+    }
+
+    map
+  }
+
+  /// Returns a shared map for the given version, which is only computed once per thread
+  pub fn for_version(version: &QRCodeVersion) -> Rc<Self> {
+    FUNCTION_PATTERN_MAPS.with(|maps| {
+      maps
+        .borrow_mut()
+        .entry(version.number())
+        .or_insert_with(|| Rc::new(FunctionPatternMap::new(version)))
+        .clone()
+    })
+  }
+
+  fn set(&mut self, x: usize, y: usize, pattern: FunctionPattern) {
+    self.patterns[x * self.number_of_modules + y] = Some(pattern);
+  }
+
+  fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, pattern: FunctionPattern) {
+    for i in x..x + width {
+      for j in y..y + height {
+        self.set(i, j, pattern);
+      }
+    }
+  }
+
+  pub fn version(&self) -> &QRCodeVersion {
+    &self.version
+  }
+
+  pub fn number_of_modules(&self) -> usize {
+    self.number_of_modules
+  }
+
+  pub fn get(&self, x: usize, y: usize) -> Option<FunctionPattern> {
+    self.patterns[x * self.number_of_modules + y]
+  }
+
+  pub fn is_function_module(&self, x: usize, y: usize) -> bool {
+    self.get(x, y).is_some()
+  }
+
+  pub fn is_data_module(&self, x: usize, y: usize) -> bool {
+    self.get(x, y).is_none()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn number_of_raw_data_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+      let number_of_alignment_patterns = version / 7 + 2;
+      modules -= (25 * number_of_alignment_patterns - 10) * number_of_alignment_patterns - 55;
+    }
+    if version >= 7 {
+      modules -= 36;
+    }
+    modules
+  }
+
+  #[test]
+  fn it_should_leave_the_expected_number_of_data_modules() {
+    for number in 1..=40 {
+      let map = FunctionPatternMap::new(&QRCodeVersion::from_number(number));
+      let number_of_modules = map.number_of_modules();
+      let data_modules = (0..number_of_modules)
+        .flat_map(|x| (0..number_of_modules).map(move |y| (x, y)))
+        .filter(|&(x, y)| map.is_data_module(x, y))
+        .count();
+
+      assert_eq!(
+        data_modules,
+        number_of_raw_data_modules(number as usize),
+        "version {}",
+        number
+      );
+    }
+  }
+}
@@ -0,0 +1,514 @@
+use std::error::Error;
+use std::fmt;
+
+const GROUP_SEPARATOR: char = '\u{1d}';
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DataLength {
+  Fixed(usize),
+  Variable(usize),
+}
+
+struct ApplicationIdentifierDefinition {
+  prefix: &'static str,
+  identifier_length: usize,
+  title: &'static str,
+  length: DataLength,
+  numeric: bool,
+  check_digit: bool,
+}
+
+macro_rules! ai {
+  ($prefix:expr, $identifier_length:expr, $title:expr, $length:expr, $numeric:expr, $check_digit:expr) => {
+    ApplicationIdentifierDefinition {
+      prefix: $prefix,
+      identifier_length: $identifier_length,
+      title: $title,
+      length: $length,
+      numeric: $numeric,
+      check_digit: $check_digit,
+    }
+  };
+}
+
+const DEFINITIONS: &[ApplicationIdentifierDefinition] = &[
+  ai!("00", 2, "SSCC", DataLength::Fixed(18), true, true),
+  ai!("01", 2, "GTIN", DataLength::Fixed(14), true, true),
+  ai!("02", 2, "CONTENT", DataLength::Fixed(14), true, true),
+  ai!("10", 2, "BATCH/LOT", DataLength::Variable(20), false, false),
+  ai!("11", 2, "PROD DATE", DataLength::Fixed(6), true, false),
+  ai!("12", 2, "DUE DATE", DataLength::Fixed(6), true, false),
+  ai!("13", 2, "PACK DATE", DataLength::Fixed(6), true, false),
+  ai!(
+    "15",
+    2,
+    "BEST BEFORE or BEST BY",
+    DataLength::Fixed(6),
+    true,
+    false
+  ),
+  ai!("16", 2, "SELL BY", DataLength::Fixed(6), true, false),
+  ai!(
+    "17",
+    2,
+    "USE BY or EXPIRY",
+    DataLength::Fixed(6),
+    true,
+    false
+  ),
+  ai!("20", 2, "VARIANT", DataLength::Fixed(2), true, false),
+  ai!("21", 2, "SERIAL", DataLength::Variable(20), false, false),
+  ai!("22", 2, "CPV", DataLength::Variable(20), false, false),
+  ai!("235", 3, "TPX", DataLength::Variable(28), false, false),
+  ai!(
+    "240",
+    3,
+    "ADDITIONAL ID",
+    DataLength::Variable(30),
+    false,
+    false
+  ),
+  ai!(
+    "241",
+    3,
+    "CUST. PART No.",
+    DataLength::Variable(30),
+    false,
+    false
+  ),
+  ai!(
+    "242",
+    3,
+    "MTO VARIANT",
+    DataLength::Variable(6),
+    true,
+    false
+  ),
+  ai!("243", 3, "PCN", DataLength::Variable(20), false, false),
+  ai!(
+    "250",
+    3,
+    "SECONDARY SERIAL",
+    DataLength::Variable(30),
+    false,
+    false
+  ),
+  ai!(
+    "251",
+    3,
+    "REF. TO SOURCE",
+    DataLength::Variable(30),
+    false,
+    false
+  ),
+  ai!("253", 3, "GDTI", DataLength::Variable(30), false, false),
+  ai!(
+    "254",
+    3,
+    "GLN EXTENSION COMPONENT",
+    DataLength::Variable(20),
+    false,
+    false
+  ),
+  ai!("255", 3, "GCN", DataLength::Variable(25), true, false),
+  ai!("30", 2, "VAR. COUNT", DataLength::Variable(8), true, false),
+  ai!(
+    "31",
+    4,
+    "NET WEIGHT, DIMENSION or AREA",
+    DataLength::Fixed(6),
+    true,
+    false
+  ),
+  ai!(
+    "32",
+    4,
+    "NET WEIGHT, DIMENSION or AREA",
+    DataLength::Fixed(6),
+    true,
+    false
+  ),
+  ai!(
+    "33",
+    4,
+    "GROSS WEIGHT, DIMENSION or VOLUME",
+    DataLength::Fixed(6),
+    true,
+    false
+  ),
+  ai!(
+    "34",
+    4,
+    "GROSS WEIGHT, DIMENSION or VOLUME",
+    DataLength::Fixed(6),
+    true,
+    false
+  ),
+  ai!(
+    "35",
+    4,
+    "AREA or NET VOLUME",
+    DataLength::Fixed(6),
+    true,
+    false
+  ),
+  ai!("36", 4, "NET VOLUME", DataLength::Fixed(6), true, false),
+  ai!("37", 2, "COUNT", DataLength::Variable(8), true, false),
+  ai!("390", 4, "AMOUNT", DataLength::Variable(15), true, false),
+  ai!("391", 4, "AMOUNT", DataLength::Variable(18), true, false),
+  ai!("392", 4, "PRICE", DataLength::Variable(15), true, false),
+  ai!("393", 4, "PRICE", DataLength::Variable(18), true, false),
+  ai!(
+    "400",
+    3,
+    "ORDER NUMBER",
+    DataLength::Variable(30),
+    false,
+    false
+  ),
+  ai!("401", 3, "GINC", DataLength::Variable(30), false, false),
+  ai!("402", 3, "GSIN", DataLength::Fixed(17), true, true),
+  ai!("403", 3, "ROUTE", DataLength::Variable(30), false, false),
+  ai!("410", 3, "SHIP TO LOC", DataLength::Fixed(13), true, true),
+  ai!("411", 3, "BILL TO", DataLength::Fixed(13), true, true),
+  ai!("412", 3, "PURCHASE FROM", DataLength::Fixed(13), true, true),
+  ai!("413", 3, "SHIP FOR LOC", DataLength::Fixed(13), true, true),
+  ai!("414", 3, "LOC No.", DataLength::Fixed(13), true, true),
+  ai!("415", 3, "PAY TO", DataLength::Fixed(13), true, true),
+  ai!("416", 3, "PROD/SERV LOC", DataLength::Fixed(13), true, true),
+  ai!("417", 3, "PARTY", DataLength::Fixed(13), true, true),
+  ai!(
+    "420",
+    3,
+    "SHIP TO POST",
+    DataLength::Variable(20),
+    false,
+    false
+  ),
+  ai!(
+    "421",
+    3,
+    "SHIP TO POST",
+    DataLength::Variable(12),
+    false,
+    false
+  ),
+  ai!("422", 3, "ORIGIN", DataLength::Fixed(3), true, false),
+  ai!(
+    "423",
+    3,
+    "COUNTRY - INITIAL PROCESS",
+    DataLength::Variable(15),
+    true,
+    false
+  ),
+  ai!(
+    "424",
+    3,
+    "COUNTRY - PROCESS",
+    DataLength::Fixed(3),
+    true,
+    false
+  ),
+  ai!(
+    "425",
+    3,
+    "COUNTRY - DISASSEMBLY",
+    DataLength::Variable(15),
+    true,
+    false
+  ),
+  ai!(
+    "426",
+    3,
+    "COUNTRY - FULL PROCESS",
+    DataLength::Fixed(3),
+    true,
+    false
+  ),
+  ai!(
+    "427",
+    3,
+    "ORIGIN SUBDIVISION",
+    DataLength::Variable(3),
+    false,
+    false
+  ),
+  ai!("7001", 4, "NSN", DataLength::Fixed(13), true, false),
+  ai!(
+    "7002",
+    4,
+    "MEAT CUT",
+    DataLength::Variable(30),
+    false,
+    false
+  ),
+  ai!("7003", 4, "EXPIRY TIME", DataLength::Fixed(10), true, false),
+  ai!(
+    "7004",
+    4,
+    "ACTIVE POTENCY",
+    DataLength::Variable(4),
+    true,
+    false
+  ),
+  ai!("8001", 4, "DIMENSIONS", DataLength::Fixed(14), true, false),
+  ai!("8002", 4, "CMT No.", DataLength::Variable(20), false, false),
+  ai!("8003", 4, "GRAI", DataLength::Variable(30), false, false),
+  ai!("8004", 4, "GIAI", DataLength::Variable(30), false, false),
+  ai!(
+    "8005",
+    4,
+    "PRICE PER UNIT",
+    DataLength::Fixed(6),
+    true,
+    false
+  ),
+  ai!("8006", 4, "ITIP", DataLength::Fixed(18), true, false),
+  ai!("8007", 4, "IBAN", DataLength::Variable(34), false, false),
+  ai!(
+    "8008",
+    4,
+    "PROD TIME",
+    DataLength::Variable(12),
+    true,
+    false
+  ),
+  ai!(
+    "8017",
+    4,
+    "GSRN - PROVIDER",
+    DataLength::Fixed(18),
+    true,
+    true
+  ),
+  ai!(
+    "8018",
+    4,
+    "GSRN - RECIPIENT",
+    DataLength::Fixed(18),
+    true,
+    true
+  ),
+  ai!("8020", 4, "REF No.", DataLength::Variable(25), false, false),
+  ai!(
+    "8200",
+    4,
+    "PRODUCT URL",
+    DataLength::Variable(70),
+    false,
+    false
+  ),
+  ai!("90", 2, "INTERNAL", DataLength::Variable(30), false, false),
+  ai!("91", 2, "INTERNAL", DataLength::Variable(90), false, false),
+  ai!("92", 2, "INTERNAL", DataLength::Variable(90), false, false),
+  ai!("93", 2, "INTERNAL", DataLength::Variable(90), false, false),
+  ai!("94", 2, "INTERNAL", DataLength::Variable(90), false, false),
+  ai!("95", 2, "INTERNAL", DataLength::Variable(90), false, false),
+  ai!("96", 2, "INTERNAL", DataLength::Variable(90), false, false),
+  ai!("97", 2, "INTERNAL", DataLength::Variable(90), false, false),
+  ai!("98", 2, "INTERNAL", DataLength::Variable(90), false, false),
+  ai!("99", 2, "INTERNAL", DataLength::Variable(90), false, false),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Gs1Error {
+  UnknownApplicationIdentifier(String),
+  InvalidLength(String),
+  NonNumericData(String),
+  InvalidCheckDigit(String),
+}
+
+impl fmt::Display for Gs1Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Gs1Error::UnknownApplicationIdentifier(identifier) => {
+        write!(f, "unknown Application Identifier {}", identifier)
+      }
+      Gs1Error::InvalidLength(data) => write!(f, "invalid length of {}", data),
+      Gs1Error::NonNumericData(identifier) => {
+        write!(
+          f,
+          "non-numeric data for Application Identifier {}",
+          identifier
+        )
+      }
+      Gs1Error::InvalidCheckDigit(identifier) => {
+        write!(
+          f,
+          "invalid check digit for Application Identifier {}",
+          identifier
+        )
+      }
+    }
+  }
+}
+
+impl Error for Gs1Error {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApplicationIdentifier {
+  pub identifier: String,
+  /// GS1 data title of the identifier, e.g. `GTIN` or `BATCH/LOT`, or `UNKNOWN` for identifiers
+  /// this crate does not know, whose value is read up to the next GS
+  pub title: &'static str,
+  pub value: String,
+}
+
+fn has_valid_check_digit(digits: &str) -> bool {
+  let sum: u32 = digits
+    .chars()
+    .rev()
+    .skip(1)
+    .enumerate()
+    .map(|(index, digit)| {
+      let value = digit.to_digit(10).unwrap_or(0);
+      if index % 2 == 0 {
+        3 * value
+      } else {
+        value
+      }
+    })
+    .sum();
+  let expected = (10 - sum % 10) % 10;
+  digits.chars().last().and_then(|digit| digit.to_digit(10)) == Some(expected)
+}
+
+/// Application Identifiers missing from the table are read as variable length data up to the
+/// next GS, with the identifier length GS1 assigns to their first two digits. Returns `None` if
+/// the element string does not start with the digits of an identifier.
+fn unknown_definition(element_string: &str) -> Option<ApplicationIdentifierDefinition> {
+  let first_two_digits: u32 = element_string.get(..2)?.parse().ok()?;
+  let identifier_length = match first_two_digits {
+    0..=22 | 30 | 37 | 90..=99 => 2,
+    23..=29 | 40..=42 | 71 => 3,
+    _ => 4,
+  };
+  let identifier = element_string.get(..identifier_length)?;
+  if !identifier.chars().all(|c| c.is_ascii_digit()) {
+    return None;
+  }
+  Some(ai!(
+    "",
+    identifier_length,
+    "UNKNOWN",
+    DataLength::Variable(90),
+    false,
+    false
+  ))
+}
+
+/// Splits a GS1 element string, with variable length fields terminated by GS, into its
+/// Application Identifiers, validating lengths, numeric data and check digits
+pub fn parse_element_string(element_string: &str) -> Result<Vec<ApplicationIdentifier>, Gs1Error> {
+  let mut remaining = element_string.trim_start_matches(GROUP_SEPARATOR);
+  let mut identifiers = vec![];
+
+  while !remaining.is_empty() {
+    let unknown;
+    let definition = match DEFINITIONS
+      .iter()
+      .find(|definition| remaining.starts_with(definition.prefix))
+    {
+      Some(definition) => definition,
+      None => {
+        unknown = unknown_definition(remaining).ok_or_else(|| {
+          Gs1Error::UnknownApplicationIdentifier(remaining.chars().take(4).collect())
+        })?;
+        &unknown
+      }
+    };
+    if remaining.len() < definition.identifier_length
+      || !remaining.is_char_boundary(definition.identifier_length)
+    {
+      return Err(Gs1Error::InvalidLength(String::from(remaining)));
+    }
+    let (identifier, rest) = remaining.split_at(definition.identifier_length);
+    let field_end = rest.find(GROUP_SEPARATOR).unwrap_or(rest.len());
+    let value_length = match definition.length {
+      DataLength::Fixed(length) => length,
+      DataLength::Variable(_) => field_end,
+    };
+    let value_is_complete = match definition.length {
+      DataLength::Fixed(length) => length <= field_end,
+      DataLength::Variable(max_length) => rest[..field_end].chars().count() <= max_length,
+    };
+    if !value_is_complete || !rest.is_char_boundary(value_length) {
+      return Err(Gs1Error::InvalidLength(String::from(identifier)));
+    }
+
+    let (value, rest) = rest.split_at(value_length);
+    if definition.numeric && !value.chars().all(|c| c.is_ascii_digit()) {
+      return Err(Gs1Error::NonNumericData(String::from(identifier)));
+    }
+    if definition.check_digit && !has_valid_check_digit(value) {
+      return Err(Gs1Error::InvalidCheckDigit(String::from(identifier)));
+    }
+
+    identifiers.push(ApplicationIdentifier {
+      identifier: String::from(identifier),
+      title: definition.title,
+      value: String::from(value),
+    });
+    remaining = rest.trim_start_matches(GROUP_SEPARATOR);
+  }
+
+  Ok(identifiers)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_should_split_fixed_and_variable_length_fields() {
+    let identifiers =
+      parse_element_string("010950110153000317250101\u{1d}10ABC123\u{1d}21XYZ\u{1d}3103000150")
+        .unwrap();
+
+    let pairs: Vec<_> = identifiers
+      .iter()
+      .map(|ai| (ai.identifier.as_str(), ai.title, ai.value.as_str()))
+      .collect();
+    assert_eq!(
+      pairs,
+      [
+        ("01", "GTIN", "09501101530003"),
+        ("17", "USE BY or EXPIRY", "250101"),
+        ("10", "BATCH/LOT", "ABC123"),
+        ("21", "SERIAL", "XYZ"),
+        ("3103", "NET WEIGHT, DIMENSION or AREA", "000150"),
+      ]
+    );
+  }
+
+  #[test]
+  fn it_should_reject_invalid_check_digits() {
+    assert_eq!(
+      parse_element_string("0109501101530004"),
+      Err(Gs1Error::InvalidCheckDigit(String::from("01")))
+    );
+  }
+
+  #[test]
+  fn it_should_read_unknown_identifiers_up_to_the_next_separator() {
+    let identifiers = parse_element_string("0109501101530003710ABC123\u{1d}10XYZ").unwrap();
+
+    let pairs: Vec<_> = identifiers
+      .iter()
+      .map(|ai| (ai.identifier.as_str(), ai.title, ai.value.as_str()))
+      .collect();
+    assert_eq!(
+      pairs,
+      [
+        ("01", "GTIN", "09501101530003"),
+        ("710", "UNKNOWN", "ABC123"),
+        ("10", "BATCH/LOT", "XYZ"),
+      ]
+    );
+    assert_eq!(
+      parse_element_string("ABC"),
+      Err(Gs1Error::UnknownApplicationIdentifier(String::from("ABC")))
+    );
+  }
+}
@@ -0,0 +1,132 @@
+type Matrix = [[f64; 3]; 3];
+
+/// A projective transform of the plane, mapping `(x, y)` to
+/// `((a x + b y + c) / (g x + h y + i), (d x + e y + f) / (g x + h y + i))`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Homography {
+  matrix: Matrix,
+}
+
+fn multiply(first: &Matrix, second: &Matrix) -> Matrix {
+  let mut result = [[0.; 3]; 3];
+  for (row, result_row) in result.iter_mut().enumerate() {
+    for (column, entry) in result_row.iter_mut().enumerate() {
+      *entry = (0..3).map(|k| first[row][k] * second[k][column]).sum();
+    }
+  }
+  result
+}
+
+/// The adjugate is the inverse up to a scalar factor, which does not matter for homographies
+fn adjugate(m: &Matrix) -> Matrix {
+  [
+    [
+      m[1][1] * m[2][2] - m[1][2] * m[2][1],
+      m[0][2] * m[2][1] - m[0][1] * m[2][2],
+      m[0][1] * m[1][2] - m[0][2] * m[1][1],
+    ],
+    [
+      m[1][2] * m[2][0] - m[1][0] * m[2][2],
+      m[0][0] * m[2][2] - m[0][2] * m[2][0],
+      m[0][2] * m[1][0] - m[0][0] * m[1][2],
+    ],
+    [
+      m[1][0] * m[2][1] - m[1][1] * m[2][0],
+      m[0][1] * m[2][0] - m[0][0] * m[2][1],
+      m[0][0] * m[1][1] - m[0][1] * m[1][0],
+    ],
+  ]
+}
+
+/// Maps the unit square corners (0, 0), (1, 0), (1, 1) and (0, 1) to the given quadrilateral
+fn square_to_quadrilateral(quadrilateral: &[(f64, f64); 4]) -> Option<Matrix> {
+  let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = *quadrilateral;
+  let dx1 = x1 - x2;
+  let dx2 = x3 - x2;
+  let dx3 = x0 - x1 + x2 - x3;
+  let dy1 = y1 - y2;
+  let dy2 = y3 - y2;
+  let dy3 = y0 - y1 + y2 - y3;
+  let denominator = dx1 * dy2 - dx2 * dy1;
+  if denominator.abs() < f64::EPSILON {
+    return None;
+  }
+  let g = (dx3 * dy2 - dx2 * dy3) / denominator;
+  let h = (dx1 * dy3 - dx3 * dy1) / denominator;
+
+  Some([
+    [x1 - x0 + g * x1, x3 - x0 + h * x3, x0],
+    [y1 - y0 + g * y1, y3 - y0 + h * y3, y0],
+    [g, h, 1.],
+  ])
+}
+
+impl Homography {
+  /// Computes the transform mapping each of the four `from` points onto the `to` point with the
+  /// same index. Both quadrilaterals have to be given in the same winding order. Returns `None`
+  /// if either of them is degenerate.
+  pub fn from_correspondences(from: &[(f64, f64); 4], to: &[(f64, f64); 4]) -> Option<Homography> {
+    let from_square = square_to_quadrilateral(from)?;
+    let to_square = square_to_quadrilateral(to)?;
+
+    Some(Homography {
+      matrix: multiply(&to_square, &adjugate(&from_square)),
+    })
+  }
+
+  pub fn map(&self, point: (f64, f64)) -> (f64, f64) {
+    let m = &self.matrix;
+    let (x, y) = point;
+    let w = m[2][0] * x + m[2][1] * y + m[2][2];
+
+    (
+      (m[0][0] * x + m[0][1] * y + m[0][2]) / w,
+      (m[1][0] * x + m[1][1] * y + m[1][2]) / w,
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_close(got: (f64, f64), expected: (f64, f64)) {
+    assert!(
+      (got.0 - expected.0).abs() < 1e-9 && (got.1 - expected.1).abs() < 1e-9,
+      "{:?} != {:?}",
+      got,
+      expected
+    );
+  }
+
+  #[test]
+  fn it_should_map_the_correspondences_onto_each_other() {
+    let from = [(3.5, 3.5), (21.5, 3.5), (18.5, 18.5), (3.5, 21.5)];
+    let to = [(10., 12.), (95., 20.), (80., 85.), (5., 110.)];
+    let homography = Homography::from_correspondences(&from, &to).unwrap();
+
+    for (&point, &expected) in from.iter().zip(to.iter()) {
+      assert_close(homography.map(point), expected);
+    }
+  }
+
+  #[test]
+  fn it_should_preserve_straight_lines() {
+    let from = [(0., 0.), (1., 0.), (1., 1.), (0., 1.)];
+    let to = [(0., 0.), (100., 10.), (90., 90.), (-5., 100.)];
+    let homography = Homography::from_correspondences(&from, &to).unwrap();
+    let (ax, ay) = homography.map((0., 0.5));
+    let (bx, by) = homography.map((0.5, 0.5));
+    let (cx, cy) = homography.map((1., 0.5));
+
+    assert!(((bx - ax) * (cy - ay) - (by - ay) * (cx - ax)).abs() < 1e-9);
+  }
+
+  #[test]
+  fn it_should_reject_degenerate_quadrilaterals() {
+    let square = [(0., 0.), (1., 0.), (1., 1.), (0., 1.)];
+    let line = [(0., 0.), (1., 1.), (2., 2.), (3., 3.)];
+
+    assert_eq!(Homography::from_correspondences(&square, &line), None);
+  }
+}
@@ -1,15 +1,32 @@
-extern crate dbscan;
+#[cfg(feature = "image")]
 extern crate image;
-extern crate imageproc;
 
+pub mod alignment_patterns;
+pub mod blocks;
+pub mod charset;
+pub mod codewords;
+pub mod config;
 pub mod decode;
+pub mod decoder;
+pub mod error;
+pub mod function_patterns;
+pub mod gs1;
+pub mod homography;
+pub mod luma;
+pub mod marker_refinement;
 pub mod math;
+pub mod mesh;
 pub mod modules;
 pub mod position;
 pub mod position_markers;
+pub mod reed_solomon;
+pub mod segments;
+mod shift_jis;
+pub mod structured_append;
 pub mod threshold;
+pub mod timing;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "image"))]
 mod tests {
     use std::env;
     use std::ffi::OsString;
@@ -65,11 +82,23 @@ mod tests {
                 .map_err(|e| format!("Could not find: {:?} {:?}", input_path, e))
                 .unwrap();
             let grayscale = image::imageops::colorops::grayscale(&img);
-            let thresholded = crate::threshold::adaptive_gaussian_threshold(&grayscale, 20., 0);
-            let hints = crate::position_markers::detect_position_marker_hints(&thresholded);
-            let markers = crate::position_markers::cluster_position_marker_hints(&hints);
-            let positions = crate::position::find_estimated_qr_code_positions(&markers);
-            let codes = crate::modules::read_modules_for_all_codes(&thresholded, &positions);
+            let config = crate::config::DecoderConfig::default();
+            let mut buffers = crate::threshold::ThresholdBuffers::default();
+            crate::threshold::detector_threshold_into(&grayscale, &config, &mut buffers);
+            let thresholded = buffers.thresholded();
+            let hints =
+                crate::position_markers::detect_position_marker_hints(thresholded, &config);
+            let markers = crate::position_markers::cluster_position_marker_hints(&hints, &config);
+            let markers = crate::marker_refinement::refine_position_markers(&grayscale, &markers);
+            let positions =
+                crate::position::find_estimated_qr_code_positions(thresholded, &markers, &config)
+                    .unwrap_or_else(|_| vec![]);
+            let codes: Vec<_> = crate::modules::read_modules_for_all_codes(
+                &grayscale, &buffers, &positions, &config,
+            )
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect();
             let format_infos: Vec<_> = codes
                 .iter()
                 .map(|c| crate::decode::decode_format_information(&c))
@@ -90,7 +119,7 @@ mod tests {
             image::imageops::replace(&mut image, &img.to_rgb(), 0, 0);
             image::imageops::replace(
                 &mut image,
-                &DynamicImage::ImageLuma8(thresholded).to_rgb(),
+                &DynamicImage::ImageLuma8(thresholded.clone().into()).to_rgb(),
                 grayscale.width(),
                 0,
             );
@@ -0,0 +1,193 @@
+#[cfg(feature = "image")]
+use image::{GrayImage, ImageBuffer};
+
+/// Read access to an 8 bit grayscale image, as needed by all stages of the detector
+pub trait LumaImage {
+  fn width(&self) -> u32;
+  fn height(&self) -> u32;
+  /// The value of the pixel at the given column and row, which have to be inside the image
+  fn luma(&self, x: u32, y: u32) -> u8;
+}
+
+/// Darkness above which a pixel, or the mean of several, is read as dark
+pub const DARKNESS_THRESHOLD: f64 = 0.5;
+
+/// Darkness of a luma value, from 0 for white to 1 for black
+pub fn darkness(luma: u8) -> f64 {
+  1. - f64::from(luma) / 255.
+}
+
+#[cfg(feature = "image")]
+impl LumaImage for GrayImage {
+  fn width(&self) -> u32 {
+    ImageBuffer::width(self)
+  }
+
+  fn height(&self) -> u32 {
+    ImageBuffer::height(self)
+  }
+
+  fn luma(&self, x: u32, y: u32) -> u8 {
+    self.get_pixel(x, y)[0]
+  }
+}
+
+/// A borrowed buffer of 8 bit luma values in row major order, whose rows start `stride` bytes
+/// apart. This is the layout of the Y plane of NV12 or YUV420 camera frames, which can be read
+/// without copying them. Use [`LumaView::new`] to check that the buffer fits the dimensions.
+#[derive(Debug, Clone, Copy)]
+pub struct LumaView<'a> {
+  data: &'a [u8],
+  width: u32,
+  height: u32,
+  stride: usize,
+}
+
+impl<'a> LumaView<'a> {
+  /// Returns `None` if the stride is smaller than the width or the buffer is too short for the
+  /// given dimensions
+  pub fn new(data: &'a [u8], width: u32, height: u32, stride: usize) -> Option<Self> {
+    let (width_in_bytes, number_of_rows) = (width as usize, height as usize);
+    let required_length = match number_of_rows {
+      0 => 0,
+      _ => (number_of_rows - 1) * stride + width_in_bytes,
+    };
+    if stride < width_in_bytes || data.len() < required_length {
+      return None;
+    }
+    Some(LumaView {
+      data,
+      width,
+      height,
+      stride,
+    })
+  }
+
+  pub fn data(&self) -> &'a [u8] {
+    self.data
+  }
+
+  /// Number of bytes between the starts of two consecutive rows
+  pub fn stride(&self) -> usize {
+    self.stride
+  }
+}
+
+impl LumaImage for LumaView<'_> {
+  fn width(&self) -> u32 {
+    self.width
+  }
+
+  fn height(&self) -> u32 {
+    self.height
+  }
+
+  fn luma(&self, x: u32, y: u32) -> u8 {
+    self.data[y as usize * self.stride + x as usize]
+  }
+}
+
+/// An owned 8 bit grayscale image without padding between its rows, as produced by the
+/// thresholds
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LumaBuffer {
+  data: Vec<u8>,
+  width: u32,
+  height: u32,
+}
+
+impl LumaBuffer {
+  /// Creates an image by calling `f` with the column and row of each pixel, row by row
+  pub fn from_fn<F>(width: u32, height: u32, f: F) -> Self
+  where
+    F: FnMut(u32, u32) -> u8,
+  {
+    let mut buffer = LumaBuffer::default();
+    buffer.fill_with(width, height, f);
+    buffer
+  }
+
+  /// Like [`LumaBuffer::from_fn`], but overwrites this image and keeps its allocation if it is
+  /// large enough
+  pub fn fill_with<F>(&mut self, width: u32, height: u32, mut f: F)
+  where
+    F: FnMut(u32, u32) -> u8,
+  {
+    self.data.clear();
+    self.data.reserve(width as usize * height as usize);
+    for y in 0..height {
+      for x in 0..width {
+        self.data.push(f(x, y));
+      }
+    }
+    self.width = width;
+    self.height = height;
+  }
+
+  /// Returns `None` if the length of the buffer does not match the dimensions
+  pub fn from_raw(width: u32, height: u32, data: Vec<u8>) -> Option<Self> {
+    if data.len() != width as usize * height as usize {
+      return None;
+    }
+    Some(LumaBuffer {
+      data,
+      width,
+      height,
+    })
+  }
+
+  pub fn put_luma(&mut self, x: u32, y: u32, value: u8) {
+    self.data[y as usize * self.width as usize + x as usize] = value;
+  }
+
+  pub fn as_view(&self) -> LumaView<'_> {
+    LumaView {
+      data: &self.data,
+      width: self.width,
+      height: self.height,
+      stride: self.width as usize,
+    }
+  }
+
+  pub fn into_raw(self) -> Vec<u8> {
+    self.data
+  }
+}
+
+impl LumaImage for LumaBuffer {
+  fn width(&self) -> u32 {
+    self.width
+  }
+
+  fn height(&self) -> u32 {
+    self.height
+  }
+
+  fn luma(&self, x: u32, y: u32) -> u8 {
+    self.data[y as usize * self.width as usize + x as usize]
+  }
+}
+
+#[cfg(feature = "image")]
+impl From<LumaBuffer> for GrayImage {
+  fn from(buffer: LumaBuffer) -> Self {
+    ImageBuffer::from_raw(buffer.width, buffer.height, buffer.data).unwrap()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_should_read_rows_with_padding() {
+    let data = [1, 2, 0, 0, 3, 4, 0, 0, 5, 6];
+    let view = LumaView::new(&data, 2, 3, 4).unwrap();
+
+    assert_eq!(view.luma(1, 0), 2);
+    assert_eq!(view.luma(0, 2), 5);
+    assert_eq!((view.width(), view.height(), view.stride()), (2, 3, 4));
+    assert!(LumaView::new(&data, 2, 4, 4).is_none());
+    assert!(LumaView::new(&data, 5, 2, 4).is_none());
+  }
+}
@@ -0,0 +1,283 @@
+use crate::luma::LumaImage;
+use crate::math::*;
+use crate::position_markers::PositionMarker;
+use std::f64::consts::FRAC_1_SQRT_2;
+
+/// Distance between two samples of a profile through a marker, in pixels
+const PROFILE_STEP: f64 = 0.25;
+/// How far the module sizes measured from the three nested squares of a marker may deviate
+/// from their mean
+const MODULE_SIZE_TOLERANCE: f64 = 0.3;
+const NUMBER_OF_ITERATIONS: usize = 3;
+
+/// Grayscale value at a sub-pixel position, interpolated bilinearly between the four closest
+/// pixel centers
+fn sample<I: LumaImage>(image: &I, point: (f64, f64)) -> Option<f64> {
+  let (x0, y0) = (point.0.floor(), point.1.floor());
+  if x0 < 0. || y0 < 0. || x0 + 1. >= f64::from(image.width()) {
+    return None;
+  }
+  if y0 + 1. >= f64::from(image.height()) {
+    return None;
+  }
+  let (fx, fy) = (point.0 - x0, point.1 - y0);
+  let (x0, y0) = (x0 as u32, y0 as u32);
+  let value = |x: u32, y: u32| f64::from(image.luma(x, y));
+
+  Some(
+    value(x0, y0) * (1. - fx) * (1. - fy)
+      + value(x0 + 1, y0) * fx * (1. - fy)
+      + value(x0, y0 + 1) * (1. - fx) * fy
+      + value(x0 + 1, y0 + 1) * fx * fy,
+  )
+}
+
+/// Initial estimate of the orientation of the marker edges in radians, in the range of -45° to
+/// 45°. Edges of a square have gradients in four directions 90° apart, so their angles are
+/// averaged after being multiplied by four, weighted by the squared gradient magnitude.
+fn estimate_orientation<I: LumaImage>(image: &I, center: (f64, f64), radius: f64) -> f64 {
+  let (mut cos_sum, mut sin_sum) = (0., 0.);
+  let x_start = (center.0 - radius).max(1.) as u32;
+  let y_start = (center.1 - radius).max(1.) as u32;
+  let x_end = ((center.0 + radius) as u32).min(image.width().saturating_sub(1));
+  let y_end = ((center.1 + radius) as u32).min(image.height().saturating_sub(1));
+  let value = |x: u32, y: u32| f64::from(image.luma(x, y));
+
+  for y in y_start..y_end {
+    for x in x_start..x_end {
+      let gradient_x = (value(x + 1, y) - value(x - 1, y)) / 2.;
+      let gradient_y = (value(x, y + 1) - value(x, y - 1)) / 2.;
+      let weight = gradient_x * gradient_x + gradient_y * gradient_y;
+      let angle = gradient_y.atan2(gradient_x);
+      cos_sum += weight * (4. * angle).cos();
+      sin_sum += weight * (4. * angle).sin();
+    }
+  }
+
+  sin_sum.atan2(cos_sum) / 4.
+}
+
+/// Positions where the profile crosses the threshold, walking outwards from its middle,
+/// interpolated linearly between the two samples around each crossing
+fn crossings(profile: &[f64], threshold: f64, middle: usize, step: i64) -> Vec<f64> {
+  let mut found = vec![];
+  let mut index = middle as i64;
+
+  while index + step >= 0 && ((index + step) as usize) < profile.len() {
+    let (current, next) = (profile[index as usize], profile[(index + step) as usize]);
+    if (current < threshold) != (next < threshold) {
+      let fraction = (threshold - current) / (next - current);
+      found.push((index - middle as i64) as f64 + fraction * step as f64);
+    }
+    index += step;
+  }
+  found
+}
+
+/// Samples a profile through the marker along the given direction and fits its edges. The
+/// profile crosses the edges of the black center (3 modules wide), of the white ring (5
+/// modules) and of the black border (7 modules) on both sides. Returns the offset of the
+/// marker center along the direction and the module size, both in pixels.
+fn measure_along<I: LumaImage>(
+  image: &I,
+  center: (f64, f64),
+  direction: (f64, f64),
+  half_length: f64,
+) -> Option<(f64, f64)> {
+  let number_of_steps = (half_length / PROFILE_STEP) as i64;
+  let profile: Vec<f64> = (-number_of_steps..=number_of_steps)
+    .map(|step| {
+      sample(
+        image,
+        vec_add(
+          center,
+          vec_scalar_mul(direction, step as f64 * PROFILE_STEP),
+        ),
+      )
+    })
+    .collect::<Option<_>>()?;
+  let min = profile.iter().cloned().fold(f64::INFINITY, f64::min);
+  let max = profile.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+  let threshold = (min + max) / 2.;
+  let middle = number_of_steps as usize;
+  if profile[middle] >= threshold {
+    return None;
+  }
+
+  let after = crossings(&profile, threshold, middle, 1);
+  let before = crossings(&profile, threshold, middle, -1);
+  if after.len() < 3 || before.len() < 3 {
+    return None;
+  }
+  let widths: Vec<f64> = (0..3)
+    .map(|index| (after[index] - before[index]) * PROFILE_STEP)
+    .collect();
+  let module_sizes = [widths[0] / 3., widths[1] / 5., widths[2] / 7.];
+  let mean_module_size = module_sizes.iter().sum::<f64>() / 3.;
+  if module_sizes
+    .iter()
+    .any(|size| (size - mean_module_size).abs() > MODULE_SIZE_TOLERANCE * mean_module_size)
+  {
+    return None;
+  }
+  // Least squares fit of the module size to the widths of 3, 5 and 7 modules
+  let module_size = (3. * widths[0] + 5. * widths[1] + 7. * widths[2]) / (9. + 25. + 49.);
+  let offset = (0..3)
+    .map(|index| (after[index] + before[index]) / 2. * PROFILE_STEP)
+    .sum::<f64>()
+    / 3.;
+
+  Some((offset, module_size))
+}
+
+/// Corrects the orientation using chords through the marker parallel to each axis, offset by
+/// one module to both sides. The midpoints of chords through a square that is rotated by an
+/// angle against the chord direction shift by the tangent of that angle per pixel of offset.
+fn measure_orientation_error<I: LumaImage>(
+  image: &I,
+  center: (f64, f64),
+  axes: [(f64, f64); 2],
+  module_size: f64,
+  half_length: f64,
+) -> Option<f64> {
+  let midpoint = |direction: (f64, f64), offset: (f64, f64)| {
+    measure_along(image, vec_add(center, offset), direction, half_length)
+      .map(|(midpoint, _)| midpoint)
+  };
+  let [axis_u, axis_v] = axes;
+  let offset_u = vec_scalar_mul(axis_u, module_size);
+  let offset_v = vec_scalar_mul(axis_v, module_size);
+  let shift_along_u =
+    midpoint(axis_u, vec_scalar_mul(offset_v, -1.))? - midpoint(axis_u, offset_v)?;
+  let shift_along_v =
+    midpoint(axis_v, offset_u)? - midpoint(axis_v, vec_scalar_mul(offset_u, -1.))?;
+
+  Some(
+    ((shift_along_u / (2. * module_size)).atan() + (shift_along_v / (2. * module_size)).atan())
+      / 2.,
+  )
+}
+
+/// Refines the center of a marker to sub-pixel precision on the grayscale image, and measures
+/// its module size along both of its axes as well as its orientation. The edges are fitted
+/// along both axes of the marker and verified along the diagonal, where the marker is wider by
+/// a factor of √2. The marker is returned unchanged if any of these checks fails.
+pub fn refine_position_marker<I: LumaImage>(image: &I, marker: &PositionMarker) -> PositionMarker {
+  let half_length = 0.6 * marker.size;
+  let mut orientation = estimate_orientation(image, marker.center, 0.6 * marker.size);
+  let mut center = marker.center;
+  let mut module_size = (0., 0.);
+
+  for _ in 0..NUMBER_OF_ITERATIONS {
+    let axis_u = (orientation.cos(), orientation.sin());
+    let axis_v = (-orientation.sin(), orientation.cos());
+    let diagonal = vec_scalar_mul(vec_add(axis_u, axis_v), FRAC_1_SQRT_2);
+    let measured_u = measure_along(image, center, axis_u, half_length);
+    let measured_v = measure_along(image, center, axis_v, half_length);
+    let measured_diagonal = measure_along(image, center, diagonal, half_length * 4. / 3.);
+    let ((offset_u, size_u), (offset_v, size_v), (_, size_diagonal)) =
+      match (measured_u, measured_v, measured_diagonal) {
+        (Some(u), Some(v), Some(diagonal)) => (u, v, diagonal),
+        _ => return marker.clone(),
+      };
+    let mean_size = (size_u + size_v) / 2.;
+    if (size_diagonal / 2f64.sqrt() - mean_size).abs() > MODULE_SIZE_TOLERANCE * mean_size {
+      return marker.clone();
+    }
+
+    center = vec_add(
+      center,
+      vec_add(
+        vec_scalar_mul(axis_u, offset_u),
+        vec_scalar_mul(axis_v, offset_v),
+      ),
+    );
+    module_size = (size_u, size_v);
+    if let Some(error) =
+      measure_orientation_error(image, center, [axis_u, axis_v], mean_size, half_length)
+    {
+      orientation += error;
+    }
+  }
+
+  PositionMarker {
+    center,
+    size: 3.5 * (module_size.0 + module_size.1),
+    module_size,
+    orientation,
+  }
+}
+
+pub fn refine_position_markers<I: LumaImage>(
+  image: &I,
+  markers: &[PositionMarker],
+) -> Vec<PositionMarker> {
+  markers
+    .iter()
+    .map(|marker| refine_position_marker(image, marker))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::luma::LumaBuffer;
+
+  /// Renders an anti-aliased finder pattern, with pixel values taken at the pixel centers
+  fn render_marker(center: (f64, f64), module_size: f64, orientation: f64) -> LumaBuffer {
+    let (cos, sin) = (orientation.cos(), orientation.sin());
+    let is_dark = |x: f64, y: f64| {
+      let (dx, dy) = (x - center.0, y - center.1);
+      let u = (cos * dx + sin * dy) / module_size;
+      let v = (-sin * dx + cos * dy) / module_size;
+      let ring = u.abs().max(v.abs());
+      ring < 1.5 || (2.5..3.5).contains(&ring)
+    };
+    let subsamples = 8;
+
+    LumaBuffer::from_fn(100, 100, |x, y| {
+      let dark = (0..subsamples * subsamples)
+        .filter(|index| {
+          let sx = (index % subsamples) as f64 + 0.5;
+          let sy = (index / subsamples) as f64 + 0.5;
+          is_dark(
+            f64::from(x) - 0.5 + sx / subsamples as f64,
+            f64::from(y) - 0.5 + sy / subsamples as f64,
+          )
+        })
+        .count();
+      (255 - 255 * dark / (subsamples * subsamples)) as u8
+    })
+  }
+
+  #[test]
+  fn it_should_refine_the_center_module_size_and_orientation() {
+    let image = render_marker((50.3, 47.7), 5.2, 0.17);
+    let marker = PositionMarker {
+      center: (51., 47.),
+      size: 35.,
+      module_size: (5., 5.),
+      orientation: 0.,
+    };
+
+    let refined = refine_position_marker(&image, &marker);
+
+    assert!(euclidean_distance(refined.center, (50.3, 47.7)) < 0.1);
+    assert!((refined.module_size.0 - 5.2).abs() < 0.1);
+    assert!((refined.module_size.1 - 5.2).abs() < 0.1);
+    assert!((refined.orientation - 0.17).abs() < 0.02);
+  }
+
+  #[test]
+  fn it_should_keep_markers_that_cannot_be_refined() {
+    let image = LumaBuffer::from_fn(100, 100, |_, _| 255);
+    let marker = PositionMarker {
+      center: (50., 50.),
+      size: 35.,
+      module_size: (5., 5.),
+      orientation: 0.,
+    };
+
+    assert_eq!(refine_position_marker(&image, &marker), marker);
+  }
+}
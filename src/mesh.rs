@@ -0,0 +1,148 @@
+use crate::alignment_patterns::{find_alignment_pattern, MAX_ALIGNMENT_PATTERN_OFFSET_IN_MODULES};
+use crate::function_patterns::alignment_pattern_centers;
+use crate::homography::Homography;
+use crate::luma::LumaImage;
+use crate::math::*;
+use crate::position::QRCodeVersion;
+
+/// Versions from which on the symbol is sampled with one transform per region between
+/// alignment patterns, as there are too many patterns for a single transform to fit all of them
+const MIN_MESH_VERSION: u32 = 7;
+
+/// Maps module coordinates to image coordinates using a grid of homographies. The grid lines
+/// run through the alignment pattern centers, each cell is mapped by the homography through
+/// its four corners. Modules outside of the outermost grid lines use the closest cell.
+#[derive(Debug, Clone)]
+pub struct HomographyMesh {
+  grid_lines: Vec<f64>,
+  cells: Vec<Homography>,
+}
+
+fn module_size_at(transform: &Homography, point: (f64, f64)) -> f64 {
+  euclidean_distance(
+    transform.map((point.0 - 0.5, point.1)),
+    transform.map((point.0 + 0.5, point.1)),
+  )
+}
+
+impl HomographyMesh {
+  /// A mesh consisting of a single cell that covers the whole symbol
+  pub fn global(transform: Homography) -> Self {
+    HomographyMesh {
+      grid_lines: vec![],
+      cells: vec![transform],
+    }
+  }
+
+  /// Locates all alignment patterns of the version around the positions predicted by the global
+  /// transform and builds a mesh through them. Grid points without an alignment pattern, like
+  /// the ones in the finder patterns, and patterns that could not be found are placed where the
+  /// global transform predicts them, as is every cell whose transform cannot be computed. Versions
+  /// without alignment patterns to build a mesh through keep the global transform.
+  pub fn new<I: LumaImage>(image: &I, transform: Homography, version: &QRCodeVersion) -> Self {
+    let centers = match alignment_pattern_centers(version) {
+      Some(centers) if version.number() >= MIN_MESH_VERSION => centers,
+      _ => return HomographyMesh::global(transform),
+    };
+    let grid_lines: Vec<f64> = centers.iter().map(|&center| center as f64 + 0.5).collect();
+    let last = grid_lines.len() - 1;
+
+    let grid_points: Vec<Vec<(f64, f64)>> = grid_lines
+      .iter()
+      .enumerate()
+      .map(|(row, &y)| {
+        grid_lines
+          .iter()
+          .enumerate()
+          .map(|(column, &x)| {
+            let predicted_center = transform.map((x, y));
+            if [(0, 0), (0, last), (last, 0)].contains(&(row, column)) {
+              return predicted_center;
+            }
+            let module_size = module_size_at(&transform, (x, y));
+            find_alignment_pattern(image, predicted_center, module_size)
+              .map(|pattern| pattern.center)
+              .filter(|&center| {
+                euclidean_distance(center, predicted_center)
+                  < MAX_ALIGNMENT_PATTERN_OFFSET_IN_MODULES * module_size
+              })
+              .unwrap_or(predicted_center)
+          })
+          .collect()
+      })
+      .collect();
+
+    let number_of_cells = grid_lines.len() - 1;
+    let mut cells = Vec::with_capacity(number_of_cells * number_of_cells);
+    for row in 0..number_of_cells {
+      for column in 0..number_of_cells {
+        let (left, right) = (grid_lines[column], grid_lines[column + 1]);
+        let (top, bottom) = (grid_lines[row], grid_lines[row + 1]);
+        let cell = Homography::from_correspondences(
+          &[(left, top), (right, top), (right, bottom), (left, bottom)],
+          &[
+            grid_points[row][column],
+            grid_points[row][column + 1],
+            grid_points[row + 1][column + 1],
+            grid_points[row + 1][column],
+          ],
+        );
+        cells.push(cell.unwrap_or_else(|| transform.clone()));
+      }
+    }
+
+    HomographyMesh {
+      grid_lines: grid_lines[1..grid_lines.len() - 1].to_vec(),
+      cells,
+    }
+  }
+
+  pub fn number_of_cells(&self) -> usize {
+    self.cells.len()
+  }
+
+  fn cell_index(&self, coordinate: f64) -> usize {
+    self
+      .grid_lines
+      .iter()
+      .take_while(|&&line| line <= coordinate)
+      .count()
+  }
+
+  pub fn map(&self, point: (f64, f64)) -> (f64, f64) {
+    let number_of_columns = self.grid_lines.len() + 1;
+    let index = self.cell_index(point.1) * number_of_columns + self.cell_index(point.0);
+    self.cells[index].map(point)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::luma::LumaBuffer;
+
+  #[test]
+  fn it_should_fall_back_to_the_global_transform_without_alignment_patterns() {
+    let version = QRCodeVersion::from_number(7);
+    let number_of_modules = f64::from(version.number_of_modules());
+    let transform = Homography::from_correspondences(
+      &[
+        (0., 0.),
+        (number_of_modules, 0.),
+        (number_of_modules, number_of_modules),
+        (0., number_of_modules),
+      ],
+      &[(10., 10.), (190., 20.), (180., 200.), (5., 190.)],
+    )
+    .unwrap();
+    let image = LumaBuffer::from_fn(220, 220, |_, _| 255);
+
+    let mesh = HomographyMesh::new(&image, transform.clone(), &version);
+
+    for &point in &[(0.5, 0.5), (20.5, 3.5), (23.5, 23.5), (44.5, 44.5)] {
+      let expected = transform.map(point);
+      let mapped = mesh.map(point);
+      assert!(euclidean_distance(mapped, expected) < 1e-6);
+    }
+  }
+}
@@ -1,100 +1,478 @@
+use crate::config::DecoderConfig;
+use crate::decode::{decode_version_information, VersionInformation};
+use crate::error::QrError;
+use crate::luma::{LumaBuffer, LumaImage};
 use crate::math::*;
+use crate::mesh::HomographyMesh;
 use crate::position::{QRCodePositionEstimation, QRCodeVersion};
+use crate::threshold::ThresholdBuffers;
+use crate::timing::TimingCalibration;
 use bitvec::BitVec;
-use image::Luma;
-use imageproc::definitions::Image;
 
+#[derive(Clone)]
 pub struct QRCodeModules {
   pub version: QRCodeVersion,
   pub bits: Vec<BitVec>,
+  /// Per column and row, how far the module is from the local mean of the threshold, from 0 if it
+  /// is as bright as the threshold to 1 if it is black or white. Only available for modules read
+  /// from an image.
+  pub confidence: Option<Vec<Vec<f64>>>,
 }
 
-fn find_marker_centers_along_border(
-  n: usize,
-  first_point: (f64, f64),
-  second_point: (f64, f64),
-) -> Vec<(f64, f64)> {
-  let vector = vec_between_points(first_point, second_point);
-  let normalized = vec_norm(vector);
-  let module_size = vec_length(vector) / n as f64;
-
-  (0..n)
-    .map(|index| {
-      vec_add(
-        first_point,
-        vec_scalar_mul(normalized, module_size * (0.5 + index as f64)),
-      )
-    })
-    .collect()
+/// The pixels averaged to read a single module
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplingKernel {
+  /// The pixel closest to the module center
+  Pixel,
+  /// A square around the module center, extending the given number of pixels to each side
+  Square(u32),
+  /// A disc around the module center, with a radius relative to the module size at that position
+  Disc(f64),
 }
 
-fn find_intersection_point(
-  section1_start: (f64, f64),
-  section1_end: (f64, f64),
-  section2_start: (f64, f64),
-  section2_end: (f64, f64),
-) -> (f64, f64) {
-  let (ax, ay) = section1_start;
-  let (bx, by) = vec_between_points(section1_start, section1_end);
-  let (cx, cy) = section2_start;
-  let (dx, dy) = vec_between_points(section2_start, section2_end);
-  let u = (bx * (cy - ay) + by * (ax - cx)) / (dx * by - dy * bx);
-
-  vec_add(section2_start, (u * dx, u * dy))
+impl Default for SamplingKernel {
+  fn default() -> Self {
+    SamplingKernel::Disc(0.3)
+  }
+}
+
+impl SamplingKernel {
+  fn pixels(self, center: (f64, f64), module_size: f64) -> Vec<(i64, i64)> {
+    let rounded_center = (center.0.round() as i64, center.1.round() as i64);
+    match self {
+      SamplingKernel::Pixel => vec![rounded_center],
+      SamplingKernel::Square(radius) => {
+        let radius = i64::from(radius);
+        (-radius..=radius)
+          .flat_map(|dx| (-radius..=radius).map(move |dy| (dx, dy)))
+          .map(|(dx, dy)| (rounded_center.0 + dx, rounded_center.1 + dy))
+          .collect()
+      }
+      SamplingKernel::Disc(relative_radius) => {
+        let radius = (relative_radius * module_size).max(0.5);
+        let extent = radius.ceil() as i64;
+        let pixels: Vec<_> = (-extent..=extent)
+          .flat_map(|dx| (-extent..=extent).map(move |dy| (dx, dy)))
+          .map(|(dx, dy)| (rounded_center.0 + dx, rounded_center.1 + dy))
+          .filter(|&(x, y)| {
+            f64::powi(x as f64 - center.0, 2) + f64::powi(y as f64 - center.1, 2) <= radius * radius
+          })
+          .collect();
+        if pixels.is_empty() {
+          vec![rounded_center]
+        } else {
+          pixels
+        }
+      }
+    }
+  }
 }
 
-fn read_modules(image: &Image<Luma<u8>>, position: &QRCodePositionEstimation) -> QRCodeModules {
-  let number_of_modules = position.version.number_of_modules() as usize;
-  let image_width_minus_1 = image.width() - 1;
-  let image_height_minus_1 = image.height() - 1;
-  let points_y_left =
-    find_marker_centers_along_border(number_of_modules, position.top_left, position.bottom_left);
-  let points_y_right =
-    find_marker_centers_along_border(number_of_modules, position.top_right, position.bottom_right);
-  let points_x_top =
-    find_marker_centers_along_border(number_of_modules, position.top_left, position.top_right);
-  let points_x_bottom = find_marker_centers_along_border(
-    number_of_modules,
-    position.bottom_left,
-    position.bottom_right,
-  );
+/// The images a code is read from. The timing and alignment patterns are located in the
+/// thresholded image, while the modules are sampled from the grayscale image and compared to the
+/// local mean the threshold was computed with.
+struct ModuleSampler<'a, I> {
+  grayscale: &'a I,
+  local_mean: &'a LumaBuffer,
+  thresholded: &'a LumaBuffer,
+  kernel: SamplingKernel,
+  threshold_offset: u8,
+}
+
+impl<I: LumaImage> ModuleSampler<'_, I> {
+  /// Averages the pixels under the kernel and their threshold, returning whether the module is
+  /// dark and its distance from the threshold relative to the distance to black or white
+  fn sample(&self, center: (f64, f64), module_size: f64) -> (bool, f64) {
+    let (width, height) = (
+      i64::from(self.grayscale.width()),
+      i64::from(self.grayscale.height()),
+    );
+    let pixels: Vec<(u32, u32)> = self
+      .kernel
+      .pixels(center, module_size)
+      .into_iter()
+      .filter(|&(x, y)| x >= 0 && y >= 0 && x < width && y < height)
+      .map(|(x, y)| (x as u32, y as u32))
+      .collect();
+    let number_of_pixels = pixels.len() as f64;
+    let luma = pixels
+      .iter()
+      .map(|&(x, y)| f64::from(self.grayscale.luma(x, y)))
+      .sum::<f64>()
+      / number_of_pixels;
+    let threshold = pixels
+      .iter()
+      .map(|&(x, y)| {
+        f64::from(
+          self
+            .local_mean
+            .luma(x, y)
+            .saturating_sub(self.threshold_offset),
+        )
+      })
+      .sum::<f64>()
+      / number_of_pixels;
+
+    if luma > threshold {
+      (false, (luma - threshold) / (255. - threshold))
+    } else {
+      (true, (threshold - luma) / threshold.max(1.))
+    }
+  }
+}
+
+fn read_modules<I: LumaImage>(
+  sampler: &ModuleSampler<I>,
+  transform: &HomographyMesh,
+  version: &QRCodeVersion,
+) -> Result<QRCodeModules, QrError> {
+  let number_of_modules = version.number_of_modules() as usize;
+  let image = sampler.thresholded;
+  let (width, height) = (f64::from(image.width()), f64::from(image.height()));
+  let calibration = TimingCalibration::measure(image, transform, version);
   let mut bits: Vec<BitVec> = Vec::with_capacity(number_of_modules);
+  let mut confidence: Vec<Vec<f64>> = Vec::with_capacity(number_of_modules);
 
   for x in 0..number_of_modules {
     let mut bits_x = BitVec::with_capacity(number_of_modules);
+    let mut confidence_x = Vec::with_capacity(number_of_modules);
 
     for y in 0..number_of_modules {
-      let point_y_left = points_y_left[y];
-      let point_y_right = points_y_right[y];
-      let point_x_top = points_x_top[x];
-      let point_x_bottom = points_x_bottom[x];
-      let intersection =
-        find_intersection_point(point_y_left, point_y_right, point_x_top, point_x_bottom);
-      let clamped_intersection = (
-        (intersection.0.max(0.).round() as u32).min(image_width_minus_1),
-        (intersection.1.max(0.).round() as u32).min(image_height_minus_1),
+      let (center_x, center_y) = calibration.module_center(x, y);
+      let module_center = transform.map((center_x, center_y));
+      let (rounded_x, rounded_y) = (module_center.0.round(), module_center.1.round());
+      if !(0. ..width).contains(&rounded_x) || !(0. ..height).contains(&rounded_y) {
+        return Err(QrError::OutOfImage);
+      }
+      let module_size = euclidean_distance(
+        transform.map((center_x - 0.5, center_y)),
+        transform.map((center_x + 0.5, center_y)),
       );
+      let (dark, module_confidence) = sampler.sample(module_center, module_size);
 
-      let pixel_at_intersection = image.get_pixel(clamped_intersection.0, clamped_intersection.1);
-
-      bits_x.push(pixel_at_intersection[0] == 0);
+      bits_x.push(dark);
+      confidence_x.push(module_confidence);
     }
 
     bits.push(bits_x);
+    confidence.push(confidence_x);
   }
 
-  QRCodeModules {
-    version: position.version.clone(),
+  Ok(QRCodeModules {
+    version: version.clone(),
     bits,
+    confidence: Some(confidence),
+  })
+}
+
+/// Reads the version information of the symbol. Version 7, the first to carry it, is easily
+/// estimated as version 6, so for that estimate the symbol is sampled as version 7 to look for
+/// it. A version 6 symbol has data modules where the blocks would be, so both have to agree, and
+/// failing to read them only means the estimate was right.
+fn read_version_information<I: LumaImage>(
+  sampler: &ModuleSampler<I>,
+  position: &QRCodePositionEstimation,
+  modules: &QRCodeModules,
+) -> Result<Option<VersionInformation>, QrError> {
+  if position.version.number() != 6 {
+    return decode_version_information(modules);
+  }
+  let version = QRCodeVersion::from_number(7);
+  let transform = position.transform_for_version(&version)?;
+  let information = read_modules(sampler, &HomographyMesh::global(transform), &version)
+    .and_then(|modules| decode_version_information(&modules));
+  Ok(match information {
+    Ok(Some(information)) if information.copies_agree => Some(information),
+    _ => None,
+  })
+}
+
+/// Reads the modules through a mesh for the estimated version. Only if the version information
+/// names a different version is the symbol sampled a second time, with a mesh for that version.
+fn read_modules_with_version_information<I: LumaImage>(
+  sampler: &ModuleSampler<I>,
+  position: &QRCodePositionEstimation,
+) -> Result<QRCodeModules, QrError> {
+  let estimated_version = &position.version;
+  let image = sampler.thresholded;
+  let mesh = HomographyMesh::new(image, position.transform.clone(), estimated_version);
+  let modules = read_modules(sampler, &mesh, estimated_version)?;
+  // Damaged version information leaves the estimated version, which the format information and
+  // the error correction still have to confirm
+  let version = match read_version_information(sampler, position, &modules) {
+    Ok(Some(version_information)) => version_information.version,
+    Ok(None) | Err(QrError::VersionUnrecoverable) => estimated_version.clone(),
+    Err(error) => return Err(error),
+  };
+  if version == *estimated_version {
+    return Ok(modules);
+  }
+
+  let transform = position.transform_for_version(&version)?;
+  let mesh = HomographyMesh::new(image, transform, &version);
+  let resampled = read_modules(sampler, &mesh, &version)?;
+  match decode_version_information(&resampled) {
+    Ok(Some(information)) if information.version != version => Err(QrError::VersionMismatch {
+      estimated: estimated_version.number(),
+      read: version.number(),
+    }),
+    _ => Ok(resampled),
   }
 }
 
-pub fn read_modules_for_all_codes(
-  image: &Image<Luma<u8>>,
+/// Reads the modules of all codes from the grayscale image, averaging the pixels under the given
+/// kernel for each module and comparing them to the local mean in `buffers`, offset like the
+/// threshold. Codes that extend beyond the image or whose version cannot be determined fail
+/// individually.
+pub fn sample_modules_for_all_codes<I: LumaImage>(
+  grayscale: &I,
+  buffers: &ThresholdBuffers,
   positions: &[QRCodePositionEstimation],
-) -> Vec<QRCodeModules> {
+  kernel: SamplingKernel,
+  threshold_offset: u8,
+) -> Vec<Result<QRCodeModules, QrError>> {
+  let sampler = ModuleSampler {
+    grayscale,
+    local_mean: buffers.local_mean(),
+    thresholded: buffers.thresholded(),
+    kernel,
+    threshold_offset,
+  };
   positions
     .iter()
-    .map(|position| read_modules(image, position))
+    .map(|position| read_modules_with_version_information(&sampler, position))
     .collect()
 }
+
+pub fn read_modules_for_all_codes<I: LumaImage>(
+  grayscale: &I,
+  buffers: &ThresholdBuffers,
+  positions: &[QRCodePositionEstimation],
+  config: &DecoderConfig,
+) -> Vec<Result<QRCodeModules, QrError>> {
+  sample_modules_for_all_codes(
+    grayscale,
+    buffers,
+    positions,
+    config.sampling_kernel,
+    config.threshold_offset,
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::codewords::data_module_positions;
+  use crate::decode::tests::{
+    add_function_patterns, modules_from_codewords, render_modules, render_modules_through,
+    NUMERIC_VERSION_1_CODEWORDS,
+  };
+  use crate::decode::{decode_payload, Mask};
+  use crate::function_patterns::FunctionPatternMap;
+  use crate::homography::Homography;
+  use crate::threshold::detector_threshold_into;
+
+  fn read_code(
+    image: &LumaBuffer,
+    position: &QRCodePositionEstimation,
+    kernel: SamplingKernel,
+  ) -> Result<QRCodeModules, QrError> {
+    let config = DecoderConfig::default();
+    let mut buffers = ThresholdBuffers::default();
+    detector_threshold_into(image, &config, &mut buffers);
+    let mut codes = sample_modules_for_all_codes(
+      image,
+      &buffers,
+      std::slice::from_ref(position),
+      kernel,
+      config.threshold_offset,
+    );
+    codes.remove(0)
+  }
+
+  #[test]
+  fn it_should_compare_the_pixels_under_the_kernel_to_the_local_mean() {
+    let image = LumaBuffer::from_fn(9, 9, |x, y| if x < 3 && y < 3 { 255 } else { 0 });
+    let local_mean = LumaBuffer::from_fn(9, 9, |_, _| 128);
+    let sampler = |kernel| ModuleSampler {
+      grayscale: &image,
+      local_mean: &local_mean,
+      thresholded: &image,
+      kernel,
+      threshold_offset: 0,
+    };
+
+    assert_eq!(
+      sampler(SamplingKernel::Pixel).sample((4., 4.), 9.),
+      (true, 1.)
+    );
+    let (dark, confidence) = sampler(SamplingKernel::Square(1)).sample((2., 2.), 9.);
+    assert!(dark);
+    assert!((confidence - (128. - 4. * 255. / 9.) / 128.).abs() < 1e-9);
+    let (dark, confidence) = sampler(SamplingKernel::Disc(0.4)).sample((1., 1.), 3.);
+    assert!(!dark);
+    assert_eq!(confidence, 1.);
+  }
+
+  #[test]
+  fn it_should_read_mid_gray_modules_as_erasures() {
+    const MODULE_SIZE: u32 = 4;
+    const QUIET_ZONE: u32 = 4;
+    let mut modules = modules_from_codewords(
+      1,
+      "101111001111100",
+      Mask::M010,
+      &NUMERIC_VERSION_1_CODEWORDS,
+    );
+    add_function_patterns(&mut modules);
+    let mut image = render_modules(&modules, MODULE_SIZE, QUIET_ZONE);
+    let positions = data_module_positions(&FunctionPatternMap::new(&modules.version));
+    let damaged = [1, 4, 7, 12, 18, 20, 23];
+    for &index in &damaged {
+      for &(x, y) in &positions[index * 8..(index + 1) * 8] {
+        for dx in 0..MODULE_SIZE {
+          for dy in 0..MODULE_SIZE {
+            let pixel_x = (x as u32 + QUIET_ZONE) * MODULE_SIZE + dx;
+            let pixel_y = (y as u32 + QUIET_ZONE) * MODULE_SIZE + dy;
+            image.put_luma(pixel_x, pixel_y, 128);
+          }
+        }
+      }
+    }
+    let to_image = |(x, y): (f64, f64)| {
+      (
+        (x + f64::from(QUIET_ZONE)) * f64::from(MODULE_SIZE),
+        (y + f64::from(QUIET_ZONE)) * f64::from(MODULE_SIZE),
+      )
+    };
+    let corners = [(0., 0.), (21., 0.), (21., 21.), (0., 21.)];
+    let position = QRCodePositionEstimation {
+      top_left: to_image(corners[0]),
+      top_right: to_image(corners[1]),
+      bottom_left: to_image(corners[3]),
+      bottom_right: to_image(corners[2]),
+      version: QRCodeVersion::from_number(1),
+      alignment_pattern: None,
+      transform: Homography::from_correspondences(
+        &corners,
+        &[
+          to_image(corners[0]),
+          to_image(corners[1]),
+          to_image(corners[2]),
+          to_image(corners[3]),
+        ],
+      )
+      .unwrap(),
+    };
+
+    let mut read = read_code(&image, &position, SamplingKernel::default()).unwrap();
+
+    let confidence = read.confidence.as_ref().unwrap();
+    for &index in &damaged {
+      for &(x, y) in &positions[index * 8..(index + 1) * 8] {
+        assert!(confidence[x][y] < 0.5);
+      }
+    }
+    let decoded = decode_payload(&read).unwrap();
+    assert_eq!(decoded.text, "01234567");
+    assert_eq!(decoded.corrections[0].erasures, damaged.len());
+    read.confidence = None;
+    assert_eq!(
+      decode_payload(&read).err(),
+      Some(QrError::TooManyErrors { block: 0 })
+    );
+  }
+
+  #[test]
+  fn it_should_read_the_version_information_of_a_code_estimated_one_version_too_small() {
+    const MODULE_SIZE: f64 = 4.;
+    const QUIET_ZONE: f64 = 4.;
+    let mut modules = modules_from_codewords(7, "101111001111100", Mask::M010, &[]);
+    add_function_patterns(&mut modules);
+    let image = render_modules(&modules, MODULE_SIZE as u32, QUIET_ZONE as u32);
+    let to_image = |(x, y): (f64, f64)| {
+      (
+        (x + QUIET_ZONE) * MODULE_SIZE,
+        (y + QUIET_ZONE) * MODULE_SIZE,
+      )
+    };
+    let transform = Homography::from_correspondences(
+      &[(3.5, 3.5), (37.5, 3.5), (41., 41.), (3.5, 37.5)],
+      &[
+        to_image((3.5, 3.5)),
+        to_image((41.5, 3.5)),
+        to_image((45., 45.)),
+        to_image((3.5, 41.5)),
+      ],
+    )
+    .unwrap();
+    let position = QRCodePositionEstimation {
+      top_left: to_image((0., 0.)),
+      top_right: to_image((45., 0.)),
+      bottom_left: to_image((0., 45.)),
+      bottom_right: to_image((45., 45.)),
+      version: QRCodeVersion::from_number(6),
+      alignment_pattern: None,
+      transform,
+    };
+
+    let read = read_code(&image, &position, SamplingKernel::Pixel);
+
+    assert_eq!(read.unwrap().bits, modules.bits);
+  }
+
+  #[test]
+  fn it_should_read_a_folded_code_through_the_mesh() {
+    const MODULE_SIZE: f64 = 4.;
+    const QUIET_ZONE: f64 = 4.;
+    // The code is folded along its middle row, which shears the rows below it to the right, by
+    // two modules at the bottom row of alignment patterns
+    const FOLD: f64 = 2.;
+    let version = QRCodeVersion::from_number(7);
+    let number_of_modules = f64::from(version.number_of_modules());
+    let shift = |y: f64| FOLD * (y - 22.5).max(0.) / 16.;
+    let to_image = |(x, y): (f64, f64)| {
+      (
+        (x + shift(y) + QUIET_ZONE) * MODULE_SIZE - 0.5,
+        (y + QUIET_ZONE) * MODULE_SIZE - 0.5,
+      )
+    };
+    let mut modules = modules_from_codewords(7, "101111001111100", Mask::M010, &[]);
+    add_function_patterns(&mut modules);
+    let size = ((number_of_modules + 2. * QUIET_ZONE + shift(number_of_modules)).ceil()
+      * MODULE_SIZE) as u32;
+    let image = render_modules_through(&modules, size, size, |(x, y)| {
+      let y = (y + 0.5) / MODULE_SIZE - QUIET_ZONE;
+      ((x + 0.5) / MODULE_SIZE - QUIET_ZONE - shift(y), y)
+    });
+    let config = DecoderConfig::default();
+    let mut buffers = ThresholdBuffers::default();
+    detector_threshold_into(&image, &config, &mut buffers);
+    let sampler = ModuleSampler {
+      grayscale: &image,
+      local_mean: buffers.local_mean(),
+      thresholded: buffers.thresholded(),
+      kernel: SamplingKernel::Pixel,
+      threshold_offset: config.threshold_offset,
+    };
+    let reference_points = [(3.5, 3.5), (41.5, 3.5), (38.5, 38.5), (3.5, 41.5)];
+    let image_points = [
+      to_image(reference_points[0]),
+      to_image(reference_points[1]),
+      to_image(reference_points[2]),
+      to_image(reference_points[3]),
+    ];
+    let transform = Homography::from_correspondences(&reference_points, &image_points).unwrap();
+
+    let global = read_modules(
+      &sampler,
+      &HomographyMesh::global(transform.clone()),
+      &version,
+    );
+    let mesh = HomographyMesh::new(buffers.thresholded(), transform, &version);
+    let read = read_modules(&sampler, &mesh, &version);
+
+    assert_ne!(global.unwrap().bits, modules.bits);
+    assert_eq!(read.unwrap().bits, modules.bits);
+  }
+}
@@ -1,27 +1,30 @@
+use crate::alignment_patterns::{
+  find_alignment_pattern, AlignmentPattern, MAX_ALIGNMENT_PATTERN_OFFSET_IN_MODULES,
+};
+use crate::config::DecoderConfig;
+use crate::error::QrError;
+use crate::homography::Homography;
+use crate::luma::LumaImage;
 use crate::math::*;
 use crate::position_markers::PositionMarker;
 
-const DIMENSIONS_THRESHOLD: f64 = 0.1;
-const MARKER_SIZE_THRESHOLD: f64 = 0.2;
-
 #[derive(Debug)]
 pub struct PositionMarkerTriple {
   pub top_left: (f64, f64),
   pub top_right: (f64, f64),
   pub bottom_left: (f64, f64),
-  pub mean_size: f64,
-}
-
-fn approx_eq(x: f64, y: f64) -> bool {
-  f64::abs(x - y) < DIMENSIONS_THRESHOLD
+  /// Mean module size of the markers along the edge to the top right and along the edge to the
+  /// bottom left marker
+  pub module_size: (f64, f64),
 }
 
-fn find_position_marker_triples(markers: &[PositionMarker]) -> Vec<PositionMarkerTriple> {
+fn find_position_marker_triples(
+  markers: &[PositionMarker],
+  config: &DecoderConfig,
+) -> Vec<PositionMarkerTriple> {
+  let approx_eq = |x: f64, y: f64| f64::abs(x - y) < config.dimensions_threshold;
   let sqrt_2 = f64::sqrt(2.);
   let number_of_markers = markers.len();
-  if number_of_markers < 3 {
-    return vec![];
-  }
 
   let mut position_marker_triples = vec![];
   let mut pairwise_distances = vec![vec![0.; number_of_markers]; number_of_markers];
@@ -59,7 +62,7 @@ fn find_position_marker_triples(markers: &[PositionMarker]) -> Vec<PositionMarke
             [marker1.size, marker2.size, marker3.size]
               .iter()
               .all(|marker_size| {
-                (marker_size - mean_marker_size) / mean_marker_size < MARKER_SIZE_THRESHOLD
+                (marker_size - mean_marker_size) / mean_marker_size < config.marker_size_threshold
               });
           let angle1 = angle(
             vec_between_points(marker1.center, marker2.center),
@@ -67,11 +70,20 @@ fn find_position_marker_triples(markers: &[PositionMarker]) -> Vec<PositionMarke
           );
 
           if angle1 < 0. && marker_sizes_match {
+            let mean_module_size_along = |direction| {
+              (marker1.module_size_along(direction)
+                + marker2.module_size_along(direction)
+                + marker3.module_size_along(direction))
+                / 3.
+            };
             position_marker_triples.push(PositionMarkerTriple {
               top_left: markers[index1].center,
               top_right: markers[index2].center,
               bottom_left: markers[index3].center,
-              mean_size: (marker1.size + marker2.size + marker3.size) / 3.,
+              module_size: (
+                mean_module_size_along(vec_between_points(marker1.center, marker2.center)),
+                mean_module_size_along(vec_between_points(marker1.center, marker3.center)),
+              ),
             });
           }
         }
@@ -82,7 +94,7 @@ fn find_position_marker_triples(markers: &[PositionMarker]) -> Vec<PositionMarke
   position_marker_triples
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct QRCodeVersion(u32);
 
 impl QRCodeVersion {
@@ -91,6 +103,14 @@ impl QRCodeVersion {
     QRCodeVersion(f_version as u32)
   }
 
+  pub fn from_number(number: u32) -> QRCodeVersion {
+    QRCodeVersion(number)
+  }
+
+  pub fn number(&self) -> u32 {
+    self.0
+  }
+
   pub fn number_of_modules(&self) -> u32 {
     4 * self.0 + 17
   }
@@ -103,59 +123,127 @@ pub struct QRCodePositionEstimation {
   pub bottom_left: (f64, f64),
   pub bottom_right: (f64, f64),
   pub version: QRCodeVersion,
+  pub alignment_pattern: Option<AlignmentPattern>,
+  /// Maps module coordinates, with `(0, 0)` being the top left corner of the symbol and
+  /// `(n, n)` the bottom right one, to image coordinates
+  pub transform: Homography,
+}
+
+/// Module coordinates of the points used to compute the transform of a symbol: the centers of
+/// the three finder patterns and either the center of the bottom right alignment pattern or the
+/// bottom right corner, in clockwise order
+fn reference_points(version: &QRCodeVersion, has_alignment_pattern: bool) -> [(f64, f64); 4] {
+  let n = f64::from(version.number_of_modules());
+  let bottom_right = if has_alignment_pattern {
+    (n - 6.5, n - 6.5)
+  } else {
+    (n, n)
+  };
+  [(3.5, 3.5), (n - 3.5, 3.5), bottom_right, (3.5, n - 3.5)]
+}
+
+impl QRCodePositionEstimation {
+  /// The transform for a different version than the estimated one, as needed when the version
+  /// information of the symbol disagrees with the estimation
+  pub fn transform_for_version(&self, version: &QRCodeVersion) -> Result<Homography, QrError> {
+    if *version == self.version {
+      return Ok(self.transform.clone());
+    }
+    let has_alignment_pattern = self.alignment_pattern.is_some();
+    let from = reference_points(version, has_alignment_pattern);
+    let old_reference_points = reference_points(&self.version, has_alignment_pattern);
+    let mut to = [(0., 0.); 4];
+    for (point, &reference_point) in to.iter_mut().zip(old_reference_points.iter()) {
+      *point = self.transform.map(reference_point);
+    }
+
+    Homography::from_correspondences(&from, &to).ok_or(QrError::DegenerateTransform)
+  }
 }
 
-pub fn find_estimated_qr_code_positions(
+/// Searches for the bottom right alignment pattern, which is positioned 6.5 modules from the
+/// bottom right corner on the diagonal through the top left finder pattern. Its predicted
+/// position is extrapolated from the three finder patterns, which are 3.5 modules from the
+/// corners. A pattern found too far from that position is taken for a misdetection, where the
+/// bound is twice the one of the mesh, as the extrapolation over the whole symbol ignores
+/// perspective.
+fn find_bottom_right_alignment_pattern<I: LumaImage>(
+  image: &I,
+  triple: &PositionMarkerTriple,
+  version: &QRCodeVersion,
+  module_size: f64,
+) -> Option<AlignmentPattern> {
+  if version.number() < 2 {
+    return None;
+  }
+  let number_of_modules = f64::from(version.number_of_modules());
+  let finder_distance = number_of_modules - 7.;
+  let alignment_distance = number_of_modules - 10.;
+  let predicted_center = vec_add(
+    triple.top_left,
+    vec_scalar_mul(
+      vec_add(
+        vec_between_points(triple.top_left, triple.top_right),
+        vec_between_points(triple.top_left, triple.bottom_left),
+      ),
+      alignment_distance / finder_distance,
+    ),
+  );
+
+  find_alignment_pattern(image, predicted_center, module_size).filter(|pattern| {
+    euclidean_distance(pattern.center, predicted_center)
+      < 2. * MAX_ALIGNMENT_PATTERN_OFFSET_IN_MODULES * module_size
+  })
+}
+
+/// Estimates the position of every code whose three finder patterns are among the markers.
+/// Fails if there are fewer than three markers or no three of them form a code of a version
+/// between 1 and 40.
+pub fn find_estimated_qr_code_positions<I: LumaImage>(
+  image: &I,
   markers: &[PositionMarker],
-) -> Vec<QRCodePositionEstimation> {
-  let position_marker_triples = find_position_marker_triples(markers);
+  config: &DecoderConfig,
+) -> Result<Vec<QRCodePositionEstimation>, QrError> {
+  if markers.len() < 3 {
+    return Err(QrError::NoFinderPatterns {
+      found: markers.len(),
+    });
+  }
+  let position_marker_triples = find_position_marker_triples(markers, config);
+  let mut unsupported_version = None;
 
-  position_marker_triples
+  let positions: Vec<_> = position_marker_triples
     .iter()
-    .map(|triple| {
-      let estimated_module_size = triple.mean_size / 7.;
-      let half_position_marker_size = 3.5 * estimated_module_size;
+    .filter_map(|triple| {
+      let (module_size_along_top, module_size_along_left) = triple.module_size;
       let top_left_to_top_right_direction =
         vec_norm(vec_between_points(triple.top_left, triple.top_right));
       let top_left_to_bottom_left_direction =
         vec_norm(vec_between_points(triple.top_left, triple.bottom_left));
-      let top_left = vec_add(
-        vec_add(
-          triple.top_left,
-          vec_scalar_mul(
-            top_left_to_top_right_direction,
-            -1. * half_position_marker_size,
-          ),
-        ),
-        vec_scalar_mul(
-          top_left_to_bottom_left_direction,
-          -1. * half_position_marker_size,
-        ),
+      // Offsets from a marker center to the outer edges of the marker along both directions
+      let to_right = vec_scalar_mul(top_left_to_top_right_direction, 3.5 * module_size_along_top);
+      let to_bottom = vec_scalar_mul(
+        top_left_to_bottom_left_direction,
+        3.5 * module_size_along_left,
       );
-      let top_right = vec_add(
-        vec_add(
-          triple.top_right,
-          vec_scalar_mul(top_left_to_top_right_direction, half_position_marker_size),
-        ),
-        vec_scalar_mul(
-          top_left_to_bottom_left_direction,
-          -1. * half_position_marker_size,
-        ),
-      );
-      let bottom_left = vec_add(
-        vec_add(
-          triple.bottom_left,
-          vec_scalar_mul(
-            top_left_to_top_right_direction,
-            -1. * half_position_marker_size,
-          ),
-        ),
-        vec_scalar_mul(top_left_to_bottom_left_direction, half_position_marker_size),
+      let (to_left, to_top) = (
+        vec_scalar_mul(to_right, -1.),
+        vec_scalar_mul(to_bottom, -1.),
       );
+      let top_left = vec_add(vec_add(triple.top_left, to_left), to_top);
+      let top_right = vec_add(vec_add(triple.top_right, to_right), to_top);
+      let bottom_left = vec_add(vec_add(triple.bottom_left, to_left), to_bottom);
       let mean_edge_length =
         (euclidean_distance(top_left, top_right) + euclidean_distance(top_left, bottom_left)) / 2.;
-      let estimated_number_of_modules = mean_edge_length / estimated_module_size;
+      let estimated_number_of_modules = (euclidean_distance(top_left, top_right)
+        / module_size_along_top
+        + euclidean_distance(top_left, bottom_left) / module_size_along_left)
+        / 2.;
       let version = QRCodeVersion::from_estimated_number_of_modules(estimated_number_of_modules);
+      if !(1..=40).contains(&version.number()) {
+        unsupported_version = Some(version.number());
+        return None;
+      }
       let number_of_modules = f64::from(version.number_of_modules());
       let module_size = mean_edge_length / number_of_modules;
       let bottom_right_1 = vec_add(
@@ -172,18 +260,85 @@ pub fn find_estimated_qr_code_positions(
           number_of_modules * module_size,
         ),
       );
-      let bottom_right = (
-        (bottom_right_1.0 + bottom_right_2.0) / 2.,
-        (bottom_right_1.1 + bottom_right_2.1) / 2.,
-      );
+      let alignment_pattern =
+        find_bottom_right_alignment_pattern(image, triple, &version, module_size);
+      let estimated_bottom_right = match &alignment_pattern {
+        Some(pattern) => pattern.center,
+        None => (
+          (bottom_right_1.0 + bottom_right_2.0) / 2.,
+          (bottom_right_1.1 + bottom_right_2.1) / 2.,
+        ),
+      };
+      let transform = Homography::from_correspondences(
+        &reference_points(&version, alignment_pattern.is_some()),
+        &[
+          triple.top_left,
+          triple.top_right,
+          estimated_bottom_right,
+          triple.bottom_left,
+        ],
+      )?;
+      let bottom_right = transform.map((number_of_modules, number_of_modules));
 
-      QRCodePositionEstimation {
+      Some(QRCodePositionEstimation {
         top_left,
         top_right,
         bottom_left,
         bottom_right,
         version,
-      }
+        alignment_pattern,
+        transform,
+      })
     })
-    .collect()
+    .collect();
+  if positions.is_empty() {
+    return Err(match unsupported_version {
+      Some(version) => QrError::UnsupportedVersion(version),
+      None => QrError::AmbiguousTriple,
+    });
+  }
+  Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::luma::LumaBuffer;
+
+  #[test]
+  fn it_should_ignore_alignment_patterns_far_from_their_prediction() {
+    const MODULE_SIZE: u32 = 4;
+    // A version 2 symbol, whose bottom right alignment pattern is predicted at (110, 110)
+    let triple = PositionMarkerTriple {
+      top_left: (50., 50.),
+      top_right: (122., 50.),
+      bottom_left: (50., 122.),
+      module_size: (4., 4.),
+    };
+    let version = QRCodeVersion::from_number(2);
+    let render = |left: u32, top: u32| {
+      LumaBuffer::from_fn(250, 250, |x, y| {
+        let module =
+          |pixel: u32, start: u32| pixel.checked_sub(start).map(|offset| offset / MODULE_SIZE);
+        let dark = match (module(x, left), module(y, top)) {
+          (Some(x), Some(y)) if x < 5 && y < 5 => u32::max(x.abs_diff(2), y.abs_diff(2)) != 1,
+          _ => false,
+        };
+        if dark {
+          0
+        } else {
+          255
+        }
+      })
+    };
+    let module_size = f64::from(MODULE_SIZE);
+
+    let near =
+      find_bottom_right_alignment_pattern(&render(100, 100), &triple, &version, module_size);
+    let far =
+      find_bottom_right_alignment_pattern(&render(140, 100), &triple, &version, module_size);
+
+    assert_eq!(near.unwrap().center, (109.5, 109.5));
+    assert_eq!(far, None);
+  }
 }
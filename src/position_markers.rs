@@ -1,11 +1,10 @@
-use image::imageops::colorops;
-use image::{DynamicImage, Luma};
-use imageproc::definitions::Image;
-use std::collections::HashMap;
+use crate::config::DecoderConfig;
+use crate::luma::LumaImage;
+use crate::math::euclidean_distance;
+#[cfg(feature = "image")]
+use image::{imageops::colorops, DynamicImage};
 
 const EXPECTED_RATIOS: [f64; 5] = [1., 1., 3., 1., 1.];
-const SYMMETRY_THRESHOLD: f64 = 0.4;
-const VARIANCE_THRESHOLD: f64 = 0.5;
 
 #[derive(Debug)]
 struct ScanResult {
@@ -19,12 +18,13 @@ struct ScanResult {
 }
 
 impl ScanResult {
+  /// Middle of the black inner run, with pixel centers at integer coordinates
   fn middle(&self) -> f64 {
     [
       f64::from(self.start),
       f64::from(self.black_border1_count),
       f64::from(self.white_inner1_count),
-      f64::from(self.black_inner_count) / 2.,
+      f64::from(self.black_inner_count - 1) / 2.,
     ]
     .iter()
     .sum()
@@ -74,11 +74,11 @@ enum ScanState {
   Found(ScanResult),
 }
 
-pub fn is_white(pixel: Luma<u8>) -> bool {
-  pixel[0] == 255
+pub fn is_white(value: u8) -> bool {
+  value == 255
 }
 
-fn is_symmetric(scan_result: &ScanResult) -> bool {
+fn is_symmetric(scan_result: &ScanResult, config: &DecoderConfig) -> bool {
   let one = [
     scan_result.black_border1_count,
     scan_result.white_inner1_count,
@@ -95,10 +95,10 @@ fn is_symmetric(scan_result: &ScanResult) -> bool {
     .map(|s| s / total)
     .sum::<f64>();
 
-  sum < SYMMETRY_THRESHOLD
+  sum < config.symmetry_threshold
 }
 
-fn ratios_match(scan_result: &ScanResult) -> bool {
+fn ratios_match(scan_result: &ScanResult, config: &DecoderConfig) -> bool {
   let scan_result_widths = [
     scan_result.black_border1_count,
     scan_result.white_inner1_count,
@@ -110,7 +110,7 @@ fn ratios_match(scan_result: &ScanResult) -> bool {
   let scan_result_total = f64::from(scan_result_widths.iter().sum::<u32>());
 
   let module_size = scan_result_total / ratios_total;
-  let max_variance = VARIANCE_THRESHOLD * module_size;
+  let max_variance = config.variance_threshold * module_size;
 
   scan_result_widths
     .iter()
@@ -118,12 +118,18 @@ fn ratios_match(scan_result: &ScanResult) -> bool {
     .all(|(width, ratio)| f64::abs(ratio * module_size - f64::from(*width)) < ratio * max_variance)
 }
 
-fn is_valid_match(scan_result: &ScanResult) -> bool {
-  is_symmetric(scan_result) && ratios_match(scan_result)
+fn is_valid_match(scan_result: &ScanResult, config: &DecoderConfig) -> bool {
+  is_symmetric(scan_result, config) && ratios_match(scan_result, config)
 }
 
-fn advance_state(state: &ScanState, pos: u32, next_pos: u32, pixel: Luma<u8>) -> (u32, ScanState) {
-  let is_white = is_white(pixel);
+fn advance_state(
+  state: &ScanState,
+  pos: u32,
+  next_pos: u32,
+  value: u8,
+  config: &DecoderConfig,
+) -> (u32, ScanState) {
+  let is_white = is_white(value);
   let new_state = match state {
     ScanState::Found { .. } => {
       if is_white {
@@ -267,7 +273,7 @@ fn advance_state(state: &ScanState, pos: u32, next_pos: u32, pixel: Luma<u8>) ->
   };
 
   if let ScanState::Found(scan_result) = &new_state {
-    if is_valid_match(scan_result) {
+    if is_valid_match(scan_result, config) {
       (next_pos, new_state)
     } else {
       (scan_result.start, ScanState::InBlack)
@@ -283,22 +289,161 @@ pub struct PositionMarkerHint {
   pub size: f64,
 }
 
-pub fn detect_position_marker_hints(image: &Image<Luma<u8>>) -> Vec<PositionMarkerHint> {
+/// How many of the candidates found along the scanlines were rejected by each cross-check
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HintStatistics {
+  pub candidates: usize,
+  pub rejected_by_perpendicular_check: usize,
+  pub rejected_by_diagonal_check: usize,
+}
+
+impl HintStatistics {
+  pub fn accepted(&self) -> usize {
+    self.candidates - self.rejected_by_perpendicular_check - self.rejected_by_diagonal_check
+  }
+}
+
+/// Number of consecutive pixels with the given color, starting next to `(x, y)` and moving by
+/// `step`, up to `limit`
+fn count_run<I: LumaImage>(
+  image: &I,
+  (x, y): (i64, i64),
+  step: (i64, i64),
+  white: bool,
+  limit: u32,
+) -> (u32, (i64, i64)) {
+  let (mut x, mut y) = (x, y);
+  let mut count = 0;
+  while count < limit {
+    let (next_x, next_y) = (x + step.0, y + step.1);
+    let inside = next_x >= 0
+      && next_y >= 0
+      && next_x < i64::from(image.width())
+      && next_y < i64::from(image.height());
+    if !inside || is_white(image.luma(next_x as u32, next_y as u32)) != white {
+      break;
+    }
+    x = next_x;
+    y = next_y;
+    count += 1;
+  }
+  (count, (x, y))
+}
+
+/// Checks the 1:1:3:1:1 ratios along the line through `center` in the direction of `step`.
+/// Returns the offset of the middle of the black center from the center pixel in steps.
+fn cross_check<I: LumaImage>(
+  image: &I,
+  center: (u32, u32),
+  step: (i64, i64),
+  size: f64,
+  config: &DecoderConfig,
+) -> Option<f64> {
+  let center = (i64::from(center.0), i64::from(center.1));
+  if is_white(image.luma(center.0 as u32, center.1 as u32)) {
+    return None;
+  }
+  let limit = size.ceil() as u32;
+  let backwards = (-step.0, -step.1);
+  let (black_inner_before, end) = count_run(image, center, backwards, false, limit);
+  let (white_inner1_count, end) = count_run(image, end, backwards, true, limit);
+  let (black_border1_count, _) = count_run(image, end, backwards, false, limit);
+  let (black_inner_after, end) = count_run(image, center, step, false, limit);
+  let (white_inner2_count, end) = count_run(image, end, step, true, limit);
+  let (black_border2_count, _) = count_run(image, end, step, false, limit);
+  let scan_result = ScanResult {
+    start: 0,
+    end: 0,
+    black_border1_count,
+    white_inner1_count,
+    black_inner_count: black_inner_before + black_inner_after + 1,
+    white_inner2_count,
+    black_border2_count,
+  };
+
+  let counts = [
+    black_border1_count,
+    white_inner1_count,
+    white_inner2_count,
+    black_border2_count,
+  ];
+  if counts.iter().all(|&count| count > 0) && is_valid_match(&scan_result, config) {
+    Some((f64::from(black_inner_after) - f64::from(black_inner_before)) / 2.)
+  } else {
+    None
+  }
+}
+
+/// Verifies a candidate found along a scanline perpendicular to it, which also yields its center
+/// along the scanline, and along both diagonals through that center
+fn verify_candidate<I: LumaImage>(
+  image: &I,
+  center: (f64, f64),
+  size: f64,
+  vertical_scanline: bool,
+  statistics: &mut HintStatistics,
+  config: &DecoderConfig,
+) -> Option<(f64, f64)> {
+  statistics.candidates += 1;
+  let center_pixel = (center.0.round() as u32, center.1.round() as u32);
+  let perpendicular = if vertical_scanline { (1, 0) } else { (0, 1) };
+  let offset = match cross_check(image, center_pixel, perpendicular, size, config) {
+    Some(offset) => offset,
+    None => {
+      statistics.rejected_by_perpendicular_check += 1;
+      return None;
+    }
+  };
+  let center = if vertical_scanline {
+    (f64::from(center_pixel.0) + offset, center.1)
+  } else {
+    (center.0, f64::from(center_pixel.1) + offset)
+  };
+
+  let center_pixel = (center.0.round() as u32, center.1.round() as u32);
+  let diagonals_match = !config.diagonal_cross_check
+    || [(1, 1), (1, -1)]
+      .iter()
+      .all(|&diagonal| cross_check(image, center_pixel, diagonal, size, config).is_some());
+  if !diagonals_match {
+    statistics.rejected_by_diagonal_check += 1;
+    return None;
+  }
+  Some(center)
+}
+
+pub fn detect_position_marker_hints<I: LumaImage>(
+  image: &I,
+  config: &DecoderConfig,
+) -> Vec<PositionMarkerHint> {
+  detect_position_marker_hints_with_statistics(image, config).0
+}
+
+/// Scans all columns and rows for the 1:1:3:1:1 pattern of a finder pattern and verifies each
+/// candidate with cross-checks, returning the hints and how many candidates were rejected
+pub fn detect_position_marker_hints_with_statistics<I: LumaImage>(
+  image: &I,
+  config: &DecoderConfig,
+) -> (Vec<PositionMarkerHint>, HintStatistics) {
   let mut found: Vec<PositionMarkerHint> = vec![];
+  let mut statistics = HintStatistics::default();
 
   for x in 0..image.width() {
     let mut state = ScanState::InWhite;
     let mut y: u32 = 0;
 
     while y < image.height() {
-      let (new_y, new_state) = advance_state(&state, y, y + 1, *image.get_pixel(x, y));
+      let (new_y, new_state) = advance_state(&state, y, y + 1, image.luma(x, y), config);
       y = new_y;
       state = new_state;
       if let ScanState::Found(scan_result) = &state {
-        found.push(PositionMarkerHint {
-          center: (f64::from(x), scan_result.middle()),
-          size: scan_result.size(),
-        });
+        let candidate = (f64::from(x), scan_result.middle());
+        let size = scan_result.size();
+        if let Some(center) =
+          verify_candidate(image, candidate, size, true, &mut statistics, config)
+        {
+          found.push(PositionMarkerHint { center, size });
+        }
       }
     }
   }
@@ -308,46 +453,104 @@ pub fn detect_position_marker_hints(image: &Image<Luma<u8>>) -> Vec<PositionMark
     let mut x: u32 = 0;
 
     while x < image.width() {
-      let (new_x, new_state) = advance_state(&state, x, x + 1, *image.get_pixel(x, y));
+      let (new_x, new_state) = advance_state(&state, x, x + 1, image.luma(x, y), config);
       x = new_x;
       state = new_state;
       if let ScanState::Found(scan_result) = &state {
-        found.push(PositionMarkerHint {
-          center: (scan_result.middle(), f64::from(y)),
-          size: scan_result.size(),
-        });
+        let candidate = (scan_result.middle(), f64::from(y));
+        let size = scan_result.size();
+        if let Some(center) =
+          verify_candidate(image, candidate, size, false, &mut statistics, config)
+        {
+          found.push(PositionMarkerHint { center, size });
+        }
       }
     }
   }
-  found
+  (found, statistics)
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct PositionMarker {
+  /// Center of the marker, with pixel centers at integer coordinates like in every other stage
   pub center: (f64, f64),
   pub size: f64,
+  /// Module size along the two axes of the marker
+  pub module_size: (f64, f64),
+  /// Rotation of the marker axes against the image axes in radians, between -45° and 45°
+  pub orientation: f64,
 }
 
-pub fn cluster_position_marker_hints(hints: &[PositionMarkerHint]) -> Vec<PositionMarker> {
-  let centers: Vec<_> = hints.iter().map(|h| vec![h.center.0, h.center.1]).collect();
-  let classifications = dbscan::cluster(4., 9, &centers);
-  let mut clusters: HashMap<usize, Vec<&PositionMarkerHint>> = HashMap::new();
-
-  for (index, c) in classifications.iter().enumerate() {
-    let hint = &hints[index];
-    match c {
-      dbscan::Classification::Noise => {}
-      dbscan::Classification::Core(cluster) | dbscan::Classification::Edge(cluster) => {
-        clusters
-          .entry(*cluster)
-          .and_modify(|v| v.push(hint))
-          .or_insert_with(|| vec![hint]);
+impl PositionMarker {
+  /// Module size along the marker axis closest to the given direction
+  pub fn module_size_along(&self, direction: (f64, f64)) -> f64 {
+    let angle = direction.1.atan2(direction.0) - self.orientation;
+    if angle.cos().abs() >= angle.sin().abs() {
+      self.module_size.0
+    } else {
+      self.module_size.1
+    }
+  }
+}
+
+/// Groups the points with DBSCAN. Points with at least `min_points` points, including
+/// themselves, closer than `eps` are core points, and every point within `eps` of a core point
+/// belongs to its cluster. Returns the indices of the points in each cluster, points belonging
+/// to no cluster are left out.
+fn dbscan(points: &[(f64, f64)], eps: f64, min_points: usize) -> Vec<Vec<usize>> {
+  let neighbours = |point: (f64, f64)| -> Vec<usize> {
+    (0..points.len())
+      .filter(|&index| euclidean_distance(point, points[index]) < eps)
+      .collect()
+  };
+  let mut visited = vec![false; points.len()];
+  let mut assigned = vec![false; points.len()];
+  let mut clusters = vec![];
+
+  for index in 0..points.len() {
+    if visited[index] {
+      continue;
+    }
+    visited[index] = true;
+    let mut queue = neighbours(points[index]);
+    if queue.len() < min_points {
+      continue;
+    }
+
+    let mut cluster = vec![index];
+    assigned[index] = true;
+    while let Some(neighbour) = queue.pop() {
+      if !assigned[neighbour] {
+        assigned[neighbour] = true;
+        cluster.push(neighbour);
+      }
+      if !visited[neighbour] {
+        visited[neighbour] = true;
+        let next_neighbours = neighbours(points[neighbour]);
+        if next_neighbours.len() >= min_points {
+          queue.extend(next_neighbours);
+        }
       }
     }
+    cluster.sort_unstable();
+    clusters.push(cluster);
   }
+  clusters
+}
+
+pub fn cluster_position_marker_hints(
+  hints: &[PositionMarkerHint],
+  config: &DecoderConfig,
+) -> Vec<PositionMarker> {
+  let centers: Vec<_> = hints.iter().map(|hint| hint.center).collect();
+  let clusters: Vec<Vec<&PositionMarkerHint>> =
+    dbscan(&centers, config.cluster_eps, config.cluster_min_points)
+      .iter()
+      .map(|indices| indices.iter().map(|&index| &hints[index]).collect())
+      .collect();
 
   let markers: Vec<_> = clusters
-    .values()
+    .iter()
     .map(|hints| {
       let number_of_hints = hints.len();
       let mean_size = hints.iter().map(|hint| hint.size).sum::<f64>() / number_of_hints as f64;
@@ -359,6 +562,8 @@ pub fn cluster_position_marker_hints(hints: &[PositionMarkerHint]) -> Vec<Positi
       PositionMarker {
         center: (mean_center_x, mean_center_y),
         size: mean_size,
+        module_size: (mean_size / 7., mean_size / 7.),
+        orientation: 0.,
       }
     })
     .collect();
@@ -366,9 +571,118 @@ pub fn cluster_position_marker_hints(hints: &[PositionMarkerHint]) -> Vec<Positi
   markers
 }
 
-pub fn detect_position_markers(image: &DynamicImage) -> Vec<PositionMarker> {
+#[cfg(feature = "image")]
+pub fn detect_position_markers(
+  image: &DynamicImage,
+  config: &DecoderConfig,
+) -> Vec<PositionMarker> {
   let grayscale = colorops::grayscale(image);
-  let thresholded = crate::threshold::adaptive_gaussian_threshold(&grayscale, 20., 0);
-  let hints = detect_position_marker_hints(&thresholded);
-  cluster_position_marker_hints(&hints)
+  let thresholded = crate::threshold::detector_threshold(&grayscale, config);
+  let hints = detect_position_marker_hints(&thresholded, config);
+  let markers = cluster_position_marker_hints(&hints, config);
+  if config.refine_position_markers {
+    crate::marker_refinement::refine_position_markers(&grayscale, &markers)
+  } else {
+    markers
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::decode::tests::is_dark_finder_pattern_module;
+  use crate::luma::LumaBuffer;
+
+  fn draw_finder_pattern(image: &mut LumaBuffer, left: u32, top: u32, module_size: u32) {
+    for y in 0..7 * module_size {
+      for x in 0..7 * module_size {
+        let dark =
+          is_dark_finder_pattern_module(i64::from(x / module_size), i64::from(y / module_size));
+        image.put_luma(left + x, top + y, if dark { 0 } else { 255 });
+      }
+    }
+  }
+
+  #[test]
+  fn it_should_accept_candidates_of_a_finder_pattern() {
+    let mut image = LumaBuffer::from_fn(100, 100, |_, _| 255);
+    draw_finder_pattern(&mut image, 30, 40, 4);
+
+    let (hints, statistics) =
+      detect_position_marker_hints_with_statistics(&image, &DecoderConfig::default());
+
+    assert!(!hints.is_empty());
+    assert_eq!(statistics.accepted(), hints.len());
+    assert_eq!(statistics.rejected_by_perpendicular_check, 0);
+    assert_eq!(statistics.rejected_by_diagonal_check, 0);
+    for hint in hints {
+      assert!((hint.center.0 - 44.).abs() <= 2.);
+      assert!((hint.center.1 - 54.).abs() <= 2.);
+    }
+  }
+
+  #[test]
+  fn it_should_reject_stripes_matching_the_ratios_along_one_axis() {
+    // Vertical bars with the widths of a finder pattern row
+    let bars = [(30, 34), (38, 50), (54, 58)];
+    let image = LumaBuffer::from_fn(100, 100, |x, y| {
+      let dark = (20..80).contains(&y) && bars.iter().any(|&(start, end)| x >= start && x < end);
+      if dark {
+        0
+      } else {
+        255
+      }
+    });
+
+    let (hints, statistics) =
+      detect_position_marker_hints_with_statistics(&image, &DecoderConfig::default());
+
+    assert!(hints.is_empty());
+    assert!(statistics.candidates > 0);
+    assert_eq!(
+      statistics.rejected_by_perpendicular_check,
+      statistics.candidates
+    );
+  }
+
+  #[test]
+  fn it_should_reject_crosses_matching_the_ratios_along_both_axes() {
+    // A plus sign whose arms read 1:1:3:1:1 horizontally and vertically, but not diagonally
+    let segments = [(30, 34), (38, 50), (54, 58)];
+    let in_segments = |value: u32| {
+      segments
+        .iter()
+        .any(|&(start, end)| value >= start && value < end)
+    };
+    let image = LumaBuffer::from_fn(100, 100, |x, y| {
+      let horizontal_arm = (38..50).contains(&y) && in_segments(x);
+      let vertical_arm = (38..50).contains(&x) && in_segments(y);
+      if horizontal_arm || vertical_arm {
+        0
+      } else {
+        255
+      }
+    });
+
+    let (hints, statistics) =
+      detect_position_marker_hints_with_statistics(&image, &DecoderConfig::default());
+
+    assert!(hints.is_empty());
+    assert!(statistics.rejected_by_diagonal_check > 0);
+  }
+
+  #[test]
+  fn it_should_take_the_module_size_along_the_closest_marker_axis() {
+    let marker = PositionMarker {
+      center: (50., 50.),
+      size: 35.,
+      module_size: (4., 6.),
+      orientation: 0.5,
+    };
+
+    assert_eq!(marker.module_size_along((1., 0.)), 4.);
+    assert_eq!(marker.module_size_along((-1., 0.)), 4.);
+    assert_eq!(marker.module_size_along((0., 1.)), 6.);
+    assert_eq!(marker.module_size_along((-1., 1.)), 6.);
+  }
 }
@@ -0,0 +1,281 @@
+use std::error::Error;
+use std::fmt;
+
+const PRIMITIVE_POLYNOMIAL: u16 = 0x11d;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Correction {
+  pub errors: usize,
+  pub erasures: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReedSolomonError {
+  TooManyErasures,
+  TooManyErrors,
+}
+
+impl fmt::Display for ReedSolomonError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ReedSolomonError::TooManyErasures => {
+        write!(f, "more erasures than error correction codewords")
+      }
+      ReedSolomonError::TooManyErrors => write!(f, "too many errors to correct"),
+    }
+  }
+}
+
+impl Error for ReedSolomonError {}
+
+pub struct GaloisField {
+  exp: [u8; 512],
+  log: [u8; 256],
+}
+
+impl GaloisField {
+  pub fn new() -> Self {
+    let mut exp = [0; 512];
+    let mut log = [0; 256];
+    let mut value: u16 = 1;
+
+    for (power, entry) in exp.iter_mut().take(255).enumerate() {
+      *entry = value as u8;
+      log[value as usize] = power as u8;
+      value <<= 1;
+      if value & 0x100 != 0 {
+        value ^= PRIMITIVE_POLYNOMIAL;
+      }
+    }
+    for power in 255..512 {
+      exp[power] = exp[power - 255];
+    }
+
+    GaloisField { exp, log }
+  }
+
+  pub fn exp(&self, power: usize) -> u8 {
+    self.exp[power % 255]
+  }
+
+  pub fn mul(&self, a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+      return 0;
+    }
+    self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+  }
+
+  pub fn inv(&self, a: u8) -> u8 {
+    debug_assert!(a != 0);
+    self.exp[255 - self.log[a as usize] as usize]
+  }
+
+  pub fn div(&self, a: u8, b: u8) -> u8 {
+    self.mul(a, self.inv(b))
+  }
+
+  /// Evaluates a polynomial stored lowest degree first
+  fn eval(&self, polynomial: &[u8], x: u8) -> u8 {
+    polynomial
+      .iter()
+      .rev()
+      .fold(0, |result, &coefficient| self.mul(result, x) ^ coefficient)
+  }
+
+  fn poly_mul(&self, first: &[u8], second: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; first.len() + second.len() - 1];
+    for (i, &a) in first.iter().enumerate() {
+      for (j, &b) in second.iter().enumerate() {
+        result[i + j] ^= self.mul(a, b);
+      }
+    }
+    result
+  }
+}
+
+impl Default for GaloisField {
+  fn default() -> Self {
+    GaloisField::new()
+  }
+}
+
+fn degree(polynomial: &[u8]) -> usize {
+  polynomial.iter().rposition(|&c| c != 0).unwrap_or(0)
+}
+
+/// Corrects a block of data codewords followed by `number_of_ec_codewords` error correction
+/// codewords in place. Indices in `erasures` mark codewords that are known to be unreliable.
+pub fn correct(
+  field: &GaloisField,
+  block: &mut [u8],
+  number_of_ec_codewords: usize,
+  erasures: &[usize],
+) -> Result<Correction, ReedSolomonError> {
+  let block_length = block.len();
+  let syndromes: Vec<u8> = (0..number_of_ec_codewords)
+    .map(|index| {
+      let root = field.exp(index);
+      block
+        .iter()
+        .fold(0, |result, &codeword| field.mul(result, root) ^ codeword)
+    })
+    .collect();
+
+  if syndromes.iter().all(|&s| s == 0) {
+    return Ok(Correction {
+      errors: 0,
+      erasures: 0,
+    });
+  }
+  if erasures.len() > number_of_ec_codewords {
+    return Err(ReedSolomonError::TooManyErasures);
+  }
+
+  let locator_of = |index: usize| field.exp(block_length - 1 - index);
+  let erasure_locator = erasures.iter().fold(vec![1], |polynomial, &index| {
+    field.poly_mul(&polynomial, &[1, locator_of(index)])
+  });
+
+  // Berlekamp-Massey, initialized with the erasure locator
+  let number_of_erasures = erasures.len();
+  let mut error_locator = erasure_locator.clone();
+  let mut previous = erasure_locator;
+  let mut length = number_of_erasures;
+
+  for step in number_of_erasures..number_of_ec_codewords {
+    let discrepancy = error_locator
+      .iter()
+      .take(length + 1)
+      .enumerate()
+      .filter(|(j, _)| *j <= step)
+      .fold(0, |result, (j, &coefficient)| {
+        result ^ field.mul(coefficient, syndromes[step - j])
+      });
+    previous.insert(0, 0);
+
+    if discrepancy == 0 {
+      continue;
+    }
+
+    let mut next = error_locator.clone();
+    if next.len() < previous.len() {
+      next.resize(previous.len(), 0);
+    }
+    for (coefficient, &b) in next.iter_mut().zip(previous.iter()) {
+      *coefficient ^= field.mul(discrepancy, b);
+    }
+
+    if 2 * length <= step + number_of_erasures {
+      let inverse = field.inv(discrepancy);
+      previous = error_locator
+        .iter()
+        .map(|&c| field.mul(c, inverse))
+        .collect();
+      length = step + 1 + number_of_erasures - length;
+    }
+    error_locator = next;
+  }
+
+  let locator_degree = degree(&error_locator);
+  if locator_degree != length
+    || 2 * (length - number_of_erasures) + number_of_erasures > number_of_ec_codewords
+  {
+    return Err(ReedSolomonError::TooManyErrors);
+  }
+
+  let error_positions: Vec<usize> = (0..block_length)
+    .filter(|&index| field.eval(&error_locator, field.inv(locator_of(index))) == 0)
+    .collect();
+  if error_positions.len() != locator_degree {
+    return Err(ReedSolomonError::TooManyErrors);
+  }
+
+  let mut evaluator = field.poly_mul(&syndromes, &error_locator);
+  evaluator.truncate(number_of_ec_codewords);
+  let derivative: Vec<u8> = error_locator
+    .iter()
+    .enumerate()
+    .skip(1)
+    .map(|(power, &coefficient)| if power % 2 == 1 { coefficient } else { 0 })
+    .collect();
+
+  for &index in &error_positions {
+    let locator = locator_of(index);
+    let locator_inverse = field.inv(locator);
+    let denominator = field.eval(&derivative, locator_inverse);
+    if denominator == 0 {
+      return Err(ReedSolomonError::TooManyErrors);
+    }
+    let magnitude = field.mul(
+      locator,
+      field.div(field.eval(&evaluator, locator_inverse), denominator),
+    );
+    block[index] ^= magnitude;
+  }
+
+  Ok(Correction {
+    errors: error_positions
+      .iter()
+      .filter(|index| !erasures.contains(index))
+      .count(),
+    erasures: number_of_erasures,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const HELLO_WORLD_1Q: [u8; 26] = [
+    0x20, 0x5b, 0x0b, 0x78, 0xd1, 0x72, 0xdc, 0x4d, 0x43, 0x40, 0xec, 0x11, 0xec, 0xa8, 0x48, 0x16,
+    0x52, 0xd9, 0x36, 0x9c, 0x00, 0x2e, 0x0f, 0xb4, 0x7a, 0x10,
+  ];
+
+  #[test]
+  fn it_should_correct_errors_up_to_half_the_ec_codewords() {
+    let field = GaloisField::new();
+    let mut block = HELLO_WORLD_1Q;
+    for &index in &[0, 3, 7, 12, 15, 25] {
+      block[index] ^= 0x5a;
+    }
+
+    let correction = correct(&field, &mut block, 13, &[]).unwrap();
+
+    assert_eq!(block, HELLO_WORLD_1Q);
+    assert_eq!(
+      correction,
+      Correction {
+        errors: 6,
+        erasures: 0
+      }
+    );
+  }
+
+  #[test]
+  fn it_should_correct_errors_and_erasures() {
+    let field = GaloisField::new();
+    let mut block = HELLO_WORLD_1Q;
+    for &index in &[1, 2, 4, 8, 9, 10, 11, 13, 20] {
+      block[index] = 0;
+    }
+    block[17] ^= 0xff;
+    block[22] ^= 0x01;
+
+    let correction = correct(&field, &mut block, 13, &[1, 2, 4, 8, 9, 10, 11, 13, 20]).unwrap();
+
+    assert_eq!(block, HELLO_WORLD_1Q);
+    assert_eq!(correction.errors, 2);
+    assert_eq!(correction.erasures, 9);
+  }
+
+  #[test]
+  fn it_should_fail_on_too_many_errors() {
+    let field = GaloisField::new();
+    let mut block = HELLO_WORLD_1Q;
+    for index in 0..9 {
+      block[index * 2] ^= 0x33;
+    }
+
+    assert!(correct(&field, &mut block, 13, &[]).is_err());
+  }
+}
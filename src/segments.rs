@@ -0,0 +1,397 @@
+use crate::charset::Charset;
+use crate::position::QRCodeVersion;
+use crate::shift_jis;
+use std::error::Error;
+use std::fmt;
+
+const ALPHANUMERIC_CHARACTERS: &[u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+const ECI_MODE_INDICATOR: u8 = 0b0111;
+const STRUCTURED_APPEND_MODE_INDICATOR: u8 = 0b0011;
+const FNC1_FIRST_POSITION_MODE_INDICATOR: u8 = 0b0101;
+const FNC1_SECOND_POSITION_MODE_INDICATOR: u8 = 0b1001;
+const GROUP_SEPARATOR: u8 = 0x1d;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentError {
+  UnsupportedMode(u8),
+  InvalidEciDesignator,
+  InvalidCharacter,
+  UnexpectedEndOfData,
+}
+
+impl fmt::Display for SegmentError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SegmentError::UnsupportedMode(indicator) => {
+        write!(f, "unsupported mode indicator {:04b}", indicator)
+      }
+      SegmentError::InvalidEciDesignator => write!(f, "invalid ECI designator"),
+      SegmentError::InvalidCharacter => write!(f, "invalid character"),
+      SegmentError::UnexpectedEndOfData => write!(f, "unexpected end of data"),
+    }
+  }
+}
+
+impl Error for SegmentError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+  Numeric,
+  Alphanumeric,
+  Byte,
+  Kanji,
+}
+
+impl Mode {
+  fn from_indicator(indicator: u8) -> Result<Self, SegmentError> {
+    match indicator {
+      0b0001 => Ok(Mode::Numeric),
+      0b0010 => Ok(Mode::Alphanumeric),
+      0b0100 => Ok(Mode::Byte),
+      0b1000 => Ok(Mode::Kanji),
+      _ => Err(SegmentError::UnsupportedMode(indicator)),
+    }
+  }
+
+  fn character_count_bits(self, version: &QRCodeVersion) -> usize {
+    let size_index = match version.number() {
+      1..=9 => 0,
+      10..=26 => 1,
+      _ => 2,
+    };
+    match self {
+      Mode::Numeric => [10, 12, 14][size_index],
+      Mode::Alphanumeric => [9, 11, 13][size_index],
+      Mode::Byte => [8, 16, 16][size_index],
+      Mode::Kanji => [8, 10, 12][size_index],
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+  pub mode: Mode,
+  /// ECI assignment number in effect for this segment, if any was designated
+  pub eci: Option<u32>,
+  /// Raw bytes of the segment, Shift_JIS encoded for kanji segments
+  pub data: Vec<u8>,
+  /// Best-effort interpretation of `data` as text
+  pub text: String,
+}
+
+/// Structured Append header of a symbol that is one part of a sequence of up to 16 symbols
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StructuredAppend {
+  /// Position of this symbol in the sequence, starting at 0
+  pub index: u8,
+  pub total: u8,
+  /// XOR of all data bytes of the complete sequence
+  pub parity: u8,
+}
+
+/// FNC1 mode of a symbol, which marks data formatted according to an industry standard
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fnc1 {
+  /// Data formatted according to the GS1 General Specifications
+  FirstPosition,
+  /// Data formatted according to a specification identified by an AIM application indicator
+  SecondPosition { application_indicator: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payload {
+  pub segments: Vec<Segment>,
+  pub structured_append: Option<StructuredAppend>,
+  pub fnc1: Option<Fnc1>,
+}
+
+struct BitReader<'a> {
+  data: &'a [u8],
+  position: usize,
+}
+
+impl<'a> BitReader<'a> {
+  fn new(data: &'a [u8]) -> Self {
+    BitReader { data, position: 0 }
+  }
+
+  fn remaining(&self) -> usize {
+    self.data.len() * 8 - self.position
+  }
+
+  fn read(&mut self, number_of_bits: usize) -> Result<u32, SegmentError> {
+    if number_of_bits > self.remaining() {
+      return Err(SegmentError::UnexpectedEndOfData);
+    }
+    let mut value = 0;
+    for _ in 0..number_of_bits {
+      let byte = self.data[self.position / 8];
+      let bit = (byte >> (7 - self.position % 8)) & 1;
+      value = (value << 1) | u32::from(bit);
+      self.position += 1;
+    }
+    Ok(value)
+  }
+}
+
+fn decode_numeric(reader: &mut BitReader, count: usize) -> Result<Vec<u8>, SegmentError> {
+  let mut data = Vec::with_capacity(count);
+  let mut remaining = count;
+  while remaining > 0 {
+    let digits = remaining.min(3);
+    let value = reader.read([0, 4, 7, 10][digits])?;
+    if value >= [1, 10, 100, 1000][digits] {
+      return Err(SegmentError::InvalidCharacter);
+    }
+    data.extend(format!("{:01$}", value, digits).bytes());
+    remaining -= digits;
+  }
+  Ok(data)
+}
+
+fn decode_alphanumeric(reader: &mut BitReader, count: usize) -> Result<Vec<u8>, SegmentError> {
+  let character = |value: u32| {
+    ALPHANUMERIC_CHARACTERS
+      .get(value as usize)
+      .cloned()
+      .ok_or(SegmentError::InvalidCharacter)
+  };
+  let mut data = Vec::with_capacity(count);
+  let mut remaining = count;
+  while remaining >= 2 {
+    let value = reader.read(11)?;
+    data.push(character(value / 45)?);
+    data.push(character(value % 45)?);
+    remaining -= 2;
+  }
+  if remaining == 1 {
+    data.push(character(reader.read(6)?)?);
+  }
+  Ok(data)
+}
+
+fn decode_byte(reader: &mut BitReader, count: usize) -> Result<Vec<u8>, SegmentError> {
+  (0..count)
+    .map(|_| reader.read(8).map(|byte| byte as u8))
+    .collect()
+}
+
+fn decode_kanji(reader: &mut BitReader, count: usize) -> Result<Vec<u8>, SegmentError> {
+  let mut data = Vec::with_capacity(2 * count);
+  for _ in 0..count {
+    let value = reader.read(13)?;
+    let assembled = ((value / 0xc0) << 8) | (value % 0xc0);
+    let shift_jis = if assembled < 0x1f00 {
+      assembled + 0x8140
+    } else {
+      assembled + 0xc140
+    };
+    data.push((shift_jis >> 8) as u8);
+    data.push(shift_jis as u8);
+  }
+  Ok(data)
+}
+
+/// In FNC1 mode `%` encodes the GS separator in alphanumeric segments and `%%` a literal `%`
+fn replace_fnc1_percent_signs(data: Vec<u8>) -> Vec<u8> {
+  let mut replaced = Vec::with_capacity(data.len());
+  let mut bytes = data.into_iter().peekable();
+  while let Some(byte) = bytes.next() {
+    if byte != b'%' {
+      replaced.push(byte);
+    } else if bytes.peek() == Some(&b'%') {
+      bytes.next();
+      replaced.push(b'%');
+    } else {
+      replaced.push(GROUP_SEPARATOR);
+    }
+  }
+  replaced
+}
+
+fn read_eci_assignment(reader: &mut BitReader) -> Result<u32, SegmentError> {
+  let first_byte = reader.read(8)?;
+  if first_byte & 0b1000_0000 == 0 {
+    Ok(first_byte)
+  } else if first_byte & 0b1100_0000 == 0b1000_0000 {
+    Ok(((first_byte & 0b0011_1111) << 8) | reader.read(8)?)
+  } else if first_byte & 0b1110_0000 == 0b1100_0000 {
+    Ok(((first_byte & 0b0001_1111) << 16) | reader.read(16)?)
+  } else {
+    Err(SegmentError::InvalidEciDesignator)
+  }
+}
+
+fn decode_text(mode: Mode, eci: Option<u32>, data: &[u8]) -> String {
+  match mode {
+    Mode::Numeric | Mode::Alphanumeric => data.iter().map(|&byte| char::from(byte)).collect(),
+    Mode::Kanji => shift_jis::decode(data),
+    Mode::Byte => match eci.and_then(Charset::from_eci_assignment) {
+      Some(charset) => charset.decode(data),
+      None => match std::str::from_utf8(data) {
+        Ok(text) => String::from(text),
+        Err(_) => data.iter().map(|&byte| char::from(byte)).collect(),
+      },
+    },
+  }
+}
+
+/// Parses the data codewords of a symbol into its segments, stopping at the terminator or at the
+/// end of the data
+pub fn parse_payload(
+  data_codewords: &[u8],
+  version: &QRCodeVersion,
+) -> Result<Payload, SegmentError> {
+  let mut reader = BitReader::new(data_codewords);
+  let mut segments = vec![];
+  let mut eci = None;
+  let mut structured_append = None;
+  let mut fnc1 = None;
+
+  while reader.remaining() >= 4 {
+    let mode = match reader.read(4)? as u8 {
+      0 => break,
+      ECI_MODE_INDICATOR => {
+        eci = Some(read_eci_assignment(&mut reader)?);
+        continue;
+      }
+      STRUCTURED_APPEND_MODE_INDICATOR => {
+        structured_append = Some(StructuredAppend {
+          index: reader.read(4)? as u8,
+          total: reader.read(4)? as u8 + 1,
+          parity: reader.read(8)? as u8,
+        });
+        continue;
+      }
+      FNC1_FIRST_POSITION_MODE_INDICATOR => {
+        fnc1 = Some(Fnc1::FirstPosition);
+        continue;
+      }
+      FNC1_SECOND_POSITION_MODE_INDICATOR => {
+        fnc1 = Some(Fnc1::SecondPosition {
+          application_indicator: reader.read(8)? as u8,
+        });
+        continue;
+      }
+      indicator => Mode::from_indicator(indicator)?,
+    };
+    let count = reader.read(mode.character_count_bits(version))? as usize;
+    let data = match mode {
+      Mode::Numeric => decode_numeric(&mut reader, count)?,
+      Mode::Alphanumeric if fnc1.is_some() => {
+        replace_fnc1_percent_signs(decode_alphanumeric(&mut reader, count)?)
+      }
+      Mode::Alphanumeric => decode_alphanumeric(&mut reader, count)?,
+      Mode::Byte => decode_byte(&mut reader, count)?,
+      Mode::Kanji => decode_kanji(&mut reader, count)?,
+    };
+    segments.push(Segment {
+      mode,
+      eci,
+      text: decode_text(mode, eci, &data),
+      data,
+    });
+  }
+
+  Ok(Payload {
+    segments,
+    structured_append,
+    fnc1,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pack_bits(bits: &str) -> Vec<u8> {
+    let bits: Vec<u8> = bits
+      .chars()
+      .filter(|c| !c.is_whitespace())
+      .map(|c| (c == '1') as u8)
+      .collect();
+    bits
+      .chunks(8)
+      .map(|chunk| {
+        chunk
+          .iter()
+          .chain(std::iter::repeat(&0))
+          .take(8)
+          .fold(0, |byte, bit| (byte << 1) | bit)
+      })
+      .collect()
+  }
+
+  #[test]
+  fn it_should_parse_mixed_segments() {
+    let data = pack_bits(
+      "0010 000001011 01100001011 01111000110 10001011100 10110111000 10011010100 001101
+       0001 0000000011 0000001100
+       1000 00000010 0110110011111 1101010101010
+       0100 00000010 11000011 10101001
+       0000",
+    );
+
+    let segments = parse_payload(&data, &QRCodeVersion::from_number(1))
+      .unwrap()
+      .segments;
+
+    let modes: Vec<_> = segments.iter().map(|segment| segment.mode).collect();
+    let texts: Vec<_> = segments
+      .iter()
+      .map(|segment| segment.text.as_str())
+      .collect();
+    assert_eq!(
+      modes,
+      [Mode::Alphanumeric, Mode::Numeric, Mode::Kanji, Mode::Byte]
+    );
+    assert_eq!(texts, ["HELLO WORLD", "012", "点茗", "é"]);
+    assert_eq!(segments[2].data, [0x93, 0x5f, 0xe4, 0xaa]);
+  }
+
+  #[test]
+  fn it_should_convert_byte_segments_using_the_designated_eci() {
+    let data = pack_bits(
+      "0111 00000100 0100 00000010 10110001 11101000
+       0111 10000000 00011010 0100 00000010 11000011 10101001
+       0000",
+    );
+
+    let segments = parse_payload(&data, &QRCodeVersion::from_number(1))
+      .unwrap()
+      .segments;
+
+    assert_eq!(segments[0].eci, Some(4));
+    assert_eq!(segments[0].text, "ąč");
+    assert_eq!(segments[1].eci, Some(26));
+    assert_eq!(segments[1].text, "é");
+  }
+
+  #[test]
+  fn it_should_parse_the_structured_append_header() {
+    let data = pack_bits("0011 0001 0011 01000001 0100 00000001 01000001 0000");
+
+    let payload = parse_payload(&data, &QRCodeVersion::from_number(1)).unwrap();
+
+    assert_eq!(
+      payload.structured_append,
+      Some(StructuredAppend {
+        index: 1,
+        total: 4,
+        parity: 0x41
+      })
+    );
+    assert_eq!(payload.segments[0].text, "A");
+  }
+
+  #[test]
+  fn it_should_replace_percent_signs_in_fnc1_mode() {
+    let data = pack_bits("0101 0010 000000101 00000101101 11011010100 100110 0000");
+
+    let payload = parse_payload(&data, &QRCodeVersion::from_number(1)).unwrap();
+
+    assert_eq!(payload.fnc1, Some(Fnc1::FirstPosition));
+    assert_eq!(payload.segments[0].data, b"10%\x1d");
+  }
+}
@@ -0,0 +1,222 @@
+use crate::decode::{decode_payload, DecodedQRCode};
+use crate::error::QrError;
+use crate::modules::QRCodeModules;
+use crate::segments::Segment;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StructuredAppendError {
+  NotStructuredAppend,
+  /// A different symbol with the same index was already added to the sequence
+  ConflictingPart,
+  IndexOutOfRange,
+  ParityMismatch,
+}
+
+impl fmt::Display for StructuredAppendError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      StructuredAppendError::NotStructuredAppend => {
+        write!(f, "the code is not part of a Structured Append sequence")
+      }
+      StructuredAppendError::ConflictingPart => {
+        write!(
+          f,
+          "a different symbol with the same index is part of the sequence"
+        )
+      }
+      StructuredAppendError::IndexOutOfRange => {
+        write!(
+          f,
+          "the symbol index is not below the total number of symbols"
+        )
+      }
+      StructuredAppendError::ParityMismatch => {
+        write!(f, "the parity does not match the assembled data")
+      }
+    }
+  }
+}
+
+impl Error for StructuredAppendError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembledPayload {
+  pub parity: u8,
+  pub segments: Vec<Segment>,
+  pub data: Vec<u8>,
+  pub text: String,
+}
+
+/// Collects the symbols of one or more Structured Append sequences, which may be spread over
+/// several images, and reassembles each sequence once all of its parts have been seen. Sequences
+/// are told apart by their parity and their total number of symbols.
+#[derive(Default)]
+pub struct StructuredAppendAssembler {
+  sequences: HashMap<(u8, u8), Vec<Option<DecodedQRCode>>>,
+}
+
+impl StructuredAppendAssembler {
+  pub fn new() -> Self {
+    StructuredAppendAssembler {
+      sequences: HashMap::new(),
+    }
+  }
+
+  /// Adds a decoded symbol, returning the assembled payload if it completed its sequence
+  pub fn add(
+    &mut self,
+    code: DecodedQRCode,
+  ) -> Result<Option<AssembledPayload>, StructuredAppendError> {
+    let header = code
+      .structured_append
+      .ok_or(StructuredAppendError::NotStructuredAppend)?;
+    if header.index >= header.total {
+      return Err(StructuredAppendError::IndexOutOfRange);
+    }
+    let key = (header.parity, header.total);
+    let parts = self
+      .sequences
+      .entry(key)
+      .or_insert_with(|| (0..header.total).map(|_| None).collect());
+
+    let part = &mut parts[header.index as usize];
+    match part {
+      Some(existing) if existing.data != code.data => {
+        return Err(StructuredAppendError::ConflictingPart)
+      }
+      _ => *part = Some(code),
+    }
+    if parts.iter().any(Option::is_none) {
+      return Ok(None);
+    }
+
+    let parts: Vec<DecodedQRCode> = self
+      .sequences
+      .remove(&key)
+      .unwrap()
+      .into_iter()
+      .flatten()
+      .collect();
+    let data: Vec<u8> = parts
+      .iter()
+      .flat_map(|part| part.data.iter().cloned())
+      .collect();
+    if data.iter().fold(0, |parity, byte| parity ^ byte) != header.parity {
+      return Err(StructuredAppendError::ParityMismatch);
+    }
+
+    Ok(Some(AssembledPayload {
+      parity: header.parity,
+      text: parts.iter().map(|part| part.text.as_str()).collect(),
+      segments: parts.into_iter().flat_map(|part| part.segments).collect(),
+      data,
+    }))
+  }
+
+  /// Decodes all codes read from an image by `read_modules_for_all_codes` and adds those that are
+  /// part of a Structured Append sequence, returning every sequence completed by them. Codes that
+  /// could not be read or decoded, or are not part of a sequence, are skipped.
+  pub fn add_modules(&mut self, codes: &[Result<QRCodeModules, QrError>]) -> Vec<AssembledPayload> {
+    codes
+      .iter()
+      .filter_map(|modules| modules.as_ref().ok())
+      .filter_map(|modules| decode_payload(modules).ok())
+      .filter_map(|code| self.add(code).ok().and_then(|payload| payload))
+      .collect()
+  }
+
+  /// Whether there are sequences of which only some parts have been seen
+  pub fn has_incomplete_sequences(&self) -> bool {
+    !self.sequences.is_empty()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::decode::{ErrorCorrectionLevel, Mask};
+  use crate::position::QRCodeVersion;
+  use crate::segments::{Mode, StructuredAppend};
+
+  fn part(index: u8, total: u8, parity: u8, text: &str) -> DecodedQRCode {
+    let segment = Segment {
+      mode: Mode::Byte,
+      eci: None,
+      data: text.as_bytes().to_vec(),
+      text: String::from(text),
+    };
+    DecodedQRCode {
+      version: QRCodeVersion::from_number(1),
+      error_correction_level: ErrorCorrectionLevel::M,
+      mask: Mask::M000,
+      segments: vec![segment],
+      structured_append: Some(StructuredAppend {
+        index,
+        total,
+        parity,
+      }),
+      fnc1: None,
+      data: text.as_bytes().to_vec(),
+      text: String::from(text),
+      corrections: vec![],
+      format_information_distance: 0,
+      corners: None,
+    }
+  }
+
+  #[test]
+  fn it_should_assemble_parts_in_order() {
+    let parity = b"abcdef".iter().fold(0, |parity, byte| parity ^ byte);
+    let mut assembler = StructuredAppendAssembler::new();
+
+    assert_eq!(assembler.add(part(2, 3, parity, "ef")), Ok(None));
+    assert_eq!(assembler.add(part(0, 3, parity, "ab")), Ok(None));
+    let payload = assembler.add(part(1, 3, parity, "cd")).unwrap().unwrap();
+
+    assert_eq!(payload.text, "abcdef");
+    assert_eq!(payload.segments.len(), 3);
+    assert!(!assembler.has_incomplete_sequences());
+  }
+
+  #[test]
+  fn it_should_reject_sequences_with_wrong_parity() {
+    let mut assembler = StructuredAppendAssembler::new();
+
+    assert_eq!(assembler.add(part(0, 2, 0x00, "ab")), Ok(None));
+    assert_eq!(
+      assembler.add(part(1, 2, 0x00, "cd")),
+      Err(StructuredAppendError::ParityMismatch)
+    );
+  }
+
+  #[test]
+  fn it_should_not_keep_sequences_of_rejected_parts() {
+    let mut assembler = StructuredAppendAssembler::new();
+
+    assert_eq!(
+      assembler.add(part(2, 2, 0x00, "ab")),
+      Err(StructuredAppendError::IndexOutOfRange)
+    );
+    assert!(!assembler.has_incomplete_sequences());
+  }
+
+  #[test]
+  fn it_should_keep_sequences_with_the_same_parity_apart() {
+    let parity = b"ab".iter().fold(0, |parity, byte| parity ^ byte);
+    let mut assembler = StructuredAppendAssembler::new();
+
+    assert_eq!(assembler.add(part(0, 3, parity, "xy")), Ok(None));
+    assert_eq!(assembler.add(part(0, 2, parity, "a")), Ok(None));
+    assert_eq!(
+      assembler.add(part(0, 2, parity, "z")),
+      Err(StructuredAppendError::ConflictingPart)
+    );
+    let payload = assembler.add(part(1, 2, parity, "b")).unwrap().unwrap();
+
+    assert_eq!(payload.text, "ab");
+    assert!(assembler.has_incomplete_sequences());
+  }
+}
@@ -1,42 +1,194 @@
-use image::Luma;
-use imageproc::definitions::Image;
-use imageproc::filter;
-
-pub fn threshold(image: &Image<Luma<u8>>, threshold: u8) -> Image<Luma<u8>> {
-  imageproc::map::map_colors(image, |color| {
-    let value = if color[0] > threshold { 255 } else { 0 };
-    image::Luma([value])
-  })
-}
+use crate::config::DecoderConfig;
+use crate::luma::{LumaBuffer, LumaImage};
 
-pub fn adaptive_box_threshold(
-  image: &Image<Luma<u8>>,
-  radius: u32,
-  diff_threshold: u8,
-) -> Image<Luma<u8>> {
-  let filtered = filter::box_filter(image, radius, radius);
-  imageproc::map::map_colors2(image, &filtered, |color, mean| {
-    let value = if color[0] > mean[0].saturating_sub(diff_threshold) {
+pub fn threshold<I: LumaImage>(image: &I, threshold: u8) -> LumaBuffer {
+  LumaBuffer::from_fn(image.width(), image.height(), |x, y| {
+    if image.luma(x, y) > threshold {
       255
     } else {
       0
-    };
-    image::Luma([value])
+    }
   })
 }
 
-pub fn adaptive_gaussian_threshold(
-  image: &Image<Luma<u8>>,
-  sigma: f32,
+/// The intermediate and resulting images of [`detector_threshold_into`], which keeps their
+/// allocations from one image to the next
+#[derive(Debug, Default)]
+pub struct ThresholdBuffers {
+  horizontal: LumaBuffer,
+  local_mean: LumaBuffer,
+  thresholded: LumaBuffer,
+}
+
+impl ThresholdBuffers {
+  /// The blurred image each pixel was compared against
+  pub fn local_mean(&self) -> &LumaBuffer {
+    &self.local_mean
+  }
+
+  pub fn thresholded(&self) -> &LumaBuffer {
+    &self.thresholded
+  }
+}
+
+/// Filters the rows and then the columns of the image with the same kernel, clamping
+/// coordinates at the image borders. The result of each pass is divided by `divisor` and
+/// rounded to 8 bits. The rows are filtered into `horizontal`, the result is written to `output`.
+fn separable_filter_into<I: LumaImage>(
+  image: &I,
+  kernel: &[f32],
+  divisor: f32,
+  horizontal: &mut LumaBuffer,
+  output: &mut LumaBuffer,
+) {
+  let (width, height) = (image.width(), image.height());
+  let radius = (kernel.len() / 2) as i64;
+  let filter = |length: u32, value: &dyn Fn(u32) -> u8, position: u32| {
+    let sum: f32 = kernel
+      .iter()
+      .enumerate()
+      .map(|(index, weight)| {
+        let offset = i64::from(position) + index as i64 - radius;
+        let clamped = offset.max(0).min(i64::from(length) - 1) as u32;
+        weight * f32::from(value(clamped))
+      })
+      .sum();
+    (sum / divisor).round().min(255.) as u8
+  };
+
+  horizontal.fill_with(width, height, |x, y| {
+    filter(width, &|x| image.luma(x, y), x)
+  });
+  output.fill_with(width, height, |x, y| {
+    filter(height, &|y| horizontal.luma(x, y), y)
+  })
+}
+
+fn separable_filter<I: LumaImage>(image: &I, kernel: &[f32], divisor: f32) -> LumaBuffer {
+  let (mut horizontal, mut output) = (LumaBuffer::default(), LumaBuffer::default());
+  separable_filter_into(image, kernel, divisor, &mut horizontal, &mut output);
+  output
+}
+
+/// Sets each pixel darker than the local mean minus `diff_threshold` to black and all others to
+/// white, where `local_mean` gives the mean for each pixel
+fn threshold_against_into<I: LumaImage>(
+  image: &I,
+  local_mean: &LumaBuffer,
   diff_threshold: u8,
-) -> Image<Luma<u8>> {
-  let filtered = filter::gaussian_blur_f32(image, sigma);
-  imageproc::map::map_colors2(image, &filtered, |color, mean| {
-    let value = if color[0] > mean[0].saturating_sub(diff_threshold) {
+  output: &mut LumaBuffer,
+) {
+  output.fill_with(image.width(), image.height(), |x, y| {
+    let mean = local_mean.luma(x, y);
+    if image.luma(x, y) > mean.saturating_sub(diff_threshold) {
       255
     } else {
       0
-    };
-    image::Luma([value])
+    }
   })
 }
+
+fn threshold_against<I: LumaImage>(
+  image: &I,
+  local_mean: &LumaBuffer,
+  diff_threshold: u8,
+) -> LumaBuffer {
+  let mut output = LumaBuffer::default();
+  threshold_against_into(image, local_mean, diff_threshold, &mut output);
+  output
+}
+
+pub fn adaptive_box_threshold<I: LumaImage>(
+  image: &I,
+  radius: u32,
+  diff_threshold: u8,
+) -> LumaBuffer {
+  let kernel_width = 2 * radius as usize + 1;
+  let filtered = separable_filter(image, &vec![1.; kernel_width], kernel_width as f32);
+  threshold_against(image, &filtered, diff_threshold)
+}
+
+/// A gaussian kernel with a radius of twice the standard deviation. It is normalized by its sum
+/// when filtering, as its truncated tails would otherwise darken the image.
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+  let radius = (2. * sigma).ceil() as usize;
+  let gaussian =
+    |x: f32| (-x * x / (2. * sigma * sigma)).exp() / ((2. * std::f32::consts::PI).sqrt() * sigma);
+  (0..=2 * radius)
+    .map(|index| gaussian(index as f32 - radius as f32))
+    .collect()
+}
+
+fn gaussian_blur<I: LumaImage>(image: &I, sigma: f32) -> LumaBuffer {
+  let kernel = gaussian_kernel(sigma);
+  separable_filter(image, &kernel, kernel.iter().sum())
+}
+
+/// Uses the gaussian blur of the image as the local mean
+pub fn adaptive_gaussian_threshold<I: LumaImage>(
+  image: &I,
+  sigma: f32,
+  diff_threshold: u8,
+) -> LumaBuffer {
+  let filtered = gaussian_blur(image, sigma);
+  threshold_against(image, &filtered, diff_threshold)
+}
+
+/// The adaptive threshold the detector runs on, with the parameters of the config
+pub fn detector_threshold<I: LumaImage>(image: &I, config: &DecoderConfig) -> LumaBuffer {
+  let mut buffers = ThresholdBuffers::default();
+  detector_threshold_into(image, config, &mut buffers);
+  buffers.thresholded
+}
+
+/// Like [`detector_threshold`], but writes the local mean and the thresholded image into the
+/// given buffers
+pub fn detector_threshold_into<I: LumaImage>(
+  image: &I,
+  config: &DecoderConfig,
+  buffers: &mut ThresholdBuffers,
+) {
+  let kernel = gaussian_kernel(config.threshold_sigma);
+  separable_filter_into(
+    image,
+    &kernel,
+    kernel.iter().sum(),
+    &mut buffers.horizontal,
+    &mut buffers.local_mean,
+  );
+  threshold_against_into(
+    image,
+    &buffers.local_mean,
+    config.threshold_offset,
+    &mut buffers.thresholded,
+  );
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_should_blur_a_uniform_image_to_itself() {
+    let image = LumaBuffer::from_fn(50, 40, |_, _| 200);
+
+    assert_eq!(gaussian_blur(&image, 10.), image);
+  }
+
+  #[test]
+  fn it_should_reuse_buffers_for_images_of_another_size() {
+    let config = DecoderConfig::default();
+    let small = LumaBuffer::from_fn(30, 20, |x, _| if x < 15 { 0 } else { 255 });
+    let large = LumaBuffer::from_fn(60, 50, |_, y| if y < 25 { 0 } else { 255 });
+    let mut buffers = ThresholdBuffers::default();
+
+    detector_threshold_into(&large, &config, &mut buffers);
+    detector_threshold_into(&small, &config, &mut buffers);
+
+    assert_eq!(buffers.thresholded(), &detector_threshold(&small, &config));
+    assert_eq!(
+      buffers.local_mean(),
+      &gaussian_blur(&small, config.threshold_sigma)
+    );
+  }
+}
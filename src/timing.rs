@@ -0,0 +1,187 @@
+use crate::luma::{darkness, LumaImage, DARKNESS_THRESHOLD};
+use crate::mesh::HomographyMesh;
+use crate::position::QRCodeVersion;
+
+/// Row of the horizontal and column of the vertical timing pattern
+const TIMING_PATTERN_INDEX: usize = 6;
+/// Distance in modules between two samples when tracing a timing pattern
+const SAMPLE_STEP: f64 = 0.1;
+/// Runs shorter than this, in modules, are treated as noise
+const MIN_RUN_LENGTH: f64 = 0.3;
+/// Measured boundaries further than this, in modules, from their expected position are ignored
+const MAX_BOUNDARY_OFFSET: f64 = 0.5;
+
+/// Corrections of the module centers measured along the timing patterns. Offsets are given in
+/// modules, per column for the horizontal and per row for the vertical timing pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimingCalibration {
+  pub column_offsets: Vec<f64>,
+  pub row_offsets: Vec<f64>,
+}
+
+#[derive(Debug)]
+struct Run {
+  dark: bool,
+  start: f64,
+  length: f64,
+}
+
+fn is_dark<I: LumaImage>(image: &I, point: (f64, f64)) -> Option<bool> {
+  if point.0 < 0. || point.1 < 0. {
+    return None;
+  }
+  let (x, y) = (point.0.round() as u32, point.1.round() as u32);
+  if x >= image.width() || y >= image.height() {
+    return None;
+  }
+  Some(darkness(image.luma(x, y)) > DARKNESS_THRESHOLD)
+}
+
+/// Samples the line between the inner dark modules of the two finder patterns and returns the
+/// runs of equal color, with runs too short to be a module merged into the preceding one
+fn trace_runs<I: LumaImage, F>(image: &I, number_of_modules: usize, to_image: F) -> Vec<Run>
+where
+  F: Fn(f64) -> (f64, f64),
+{
+  let first = TIMING_PATTERN_INDEX as f64 + SAMPLE_STEP / 2.;
+  let last = (number_of_modules - TIMING_PATTERN_INDEX) as f64 - SAMPLE_STEP / 2.;
+  let number_of_samples = ((last - first) / SAMPLE_STEP) as usize + 1;
+  let mut runs: Vec<Run> = vec![];
+
+  for index in 0..number_of_samples {
+    let position = first + index as f64 * SAMPLE_STEP;
+    let dark = match is_dark(image, to_image(position)) {
+      Some(dark) => dark,
+      None => continue,
+    };
+    match runs.last_mut() {
+      Some(run) if run.dark == dark => run.length += SAMPLE_STEP,
+      _ => runs.push(Run {
+        dark,
+        start: position - SAMPLE_STEP / 2.,
+        length: SAMPLE_STEP,
+      }),
+    }
+  }
+
+  let mut merged: Vec<Run> = vec![];
+  for run in runs {
+    match merged.last_mut() {
+      Some(previous) if run.length < MIN_RUN_LENGTH || previous.dark == run.dark => {
+        previous.length += run.length
+      }
+      _ => merged.push(run),
+    }
+  }
+  merged
+}
+
+/// Offsets of the module centers along one timing pattern. Each measured boundary is matched
+/// to the expected module boundary it is closest to, where the module starting there has to be
+/// dark for even indices. The center of a module is the middle between its two boundaries,
+/// which is unaffected by dark modules bleeding into light ones. Modules without two measured
+/// boundaries keep their uncorrected position.
+fn measure_offsets(runs: &[Run], number_of_modules: usize) -> Vec<f64> {
+  let mut boundaries: Vec<Option<f64>> = vec![None; number_of_modules + 1];
+  for run in runs.iter().skip(1) {
+    let index = run.start.round() as usize;
+    let expected_dark = index.is_multiple_of(2);
+    let in_timing_pattern =
+      index > TIMING_PATTERN_INDEX && index < number_of_modules - TIMING_PATTERN_INDEX;
+    if in_timing_pattern
+      && run.dark == expected_dark
+      && (run.start - index as f64).abs() < MAX_BOUNDARY_OFFSET
+    {
+      boundaries[index] = Some(run.start);
+    }
+  }
+
+  (0..number_of_modules)
+    .map(|index| match (boundaries[index], boundaries[index + 1]) {
+      (Some(start), Some(end)) => (start + end) / 2. - (index as f64 + 0.5),
+      _ => 0.,
+    })
+    .collect()
+}
+
+impl TimingCalibration {
+  /// Traces both timing patterns through the given transform
+  pub fn measure<I: LumaImage>(
+    image: &I,
+    transform: &HomographyMesh,
+    version: &QRCodeVersion,
+  ) -> Self {
+    let number_of_modules = version.number_of_modules() as usize;
+    let center = TIMING_PATTERN_INDEX as f64 + 0.5;
+    let horizontal = trace_runs(image, number_of_modules, |x| transform.map((x, center)));
+    let vertical = trace_runs(image, number_of_modules, |y| transform.map((center, y)));
+
+    TimingCalibration {
+      column_offsets: measure_offsets(&horizontal, number_of_modules),
+      row_offsets: measure_offsets(&vertical, number_of_modules),
+    }
+  }
+
+  /// Corrected center of the module at the given column and row, in module coordinates
+  pub fn module_center(&self, x: usize, y: usize) -> (f64, f64) {
+    (
+      x as f64 + 0.5 + self.column_offsets[x],
+      y as f64 + 0.5 + self.row_offsets[y],
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::homography::Homography;
+  use crate::luma::LumaBuffer;
+
+  #[test]
+  fn it_should_measure_shifted_module_centers_along_the_timing_pattern() {
+    let version = QRCodeVersion::from_number(1);
+    let square = [(0., 0.), (21., 0.), (21., 21.), (0., 21.)];
+    let scaled = [(0., 0.), (210., 0.), (210., 210.), (0., 210.)];
+    let transform =
+      HomographyMesh::global(Homography::from_correspondences(&square, &scaled).unwrap());
+    // Module boundaries of the horizontal timing pattern are shifted by 0.4 modules between
+    // its two finder patterns
+    let boundary = |index: u32| {
+      if (7..=14).contains(&index) {
+        10 * index + 4
+      } else {
+        10 * index
+      }
+    };
+    let image = LumaBuffer::from_fn(210, 210, |x, y| {
+      let column = (0..21)
+        .rev()
+        .find(|&index| boundary(index) <= x)
+        .unwrap_or(0);
+      let dark = (60..70).contains(&y) && column % 2 == 0;
+      if dark {
+        0
+      } else {
+        255
+      }
+    });
+
+    let calibration = TimingCalibration::measure(&image, &transform, &version);
+
+    for column in 7..14 {
+      assert!((calibration.column_offsets[column] - 0.4).abs() < 0.15);
+    }
+    assert!(calibration.row_offsets.iter().all(|&offset| offset == 0.));
+    assert_eq!(calibration.column_offsets[3], 0.);
+  }
+
+  #[test]
+  fn it_should_read_grayscale_pixels_against_the_module_threshold() {
+    let image = LumaBuffer::from_fn(3, 1, |x, _| [100, 128, 200][x as usize]);
+
+    assert_eq!(is_dark(&image, (0., 0.)), Some(true));
+    assert_eq!(is_dark(&image, (1., 0.)), Some(false));
+    assert_eq!(is_dark(&image, (2., 0.)), Some(false));
+    assert_eq!(is_dark(&image, (3., 0.)), None);
+  }
+}